@@ -0,0 +1,180 @@
+use jamo::{decompose, char_jamo, edit_distance};
+use scheduler::Grade;
+
+/// A jamo that differs between the expected and typed syllable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JamoDiff {
+    /// 'initial', 'vowel' or 'batchim'.
+    pub part: &'static str,
+    pub expected: Option<char>,
+    pub typed: Option<char>,
+}
+
+/// How a syllable of the expected answer compares to the typed one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyllableDiff {
+    Same(char),
+    Changed {
+        expected: char,
+        typed: char,
+        jamo: Vec<JamoDiff>,
+    },
+    Missing(char),
+    Extra(char),
+}
+
+/// The result of comparing a typed answer to the expected one, jamo by jamo.
+#[derive(Debug, Clone)]
+pub struct AnswerCheck {
+    pub diffs: Vec<SyllableDiff>,
+    /// How many jamo had to be changed to get the expected answer.
+    pub distance: usize,
+    /// How many jamo the expected answer has.
+    pub n_jamo: usize,
+}
+
+fn normalize(text: &str) -> Vec<char> {
+    text.split_whitespace().collect::<Vec<_>>().join(" ").chars().collect()
+}
+
+fn jamo_diffs(expected: char, typed: char) -> Vec<JamoDiff> {
+    let (e, t) = match (decompose(expected), decompose(typed)) {
+        (Some(e), Some(t)) => (e, t),
+        _ => return Vec::new(),
+    };
+    let parts = [("initial", Some(e.initial), Some(t.initial)),
+                 ("vowel", Some(e.medial), Some(t.medial)),
+                 ("batchim", e.final_, t.final_)];
+    parts.iter()
+        .filter(|&&(_, e, t)| e != t)
+        .map(|&(part, e, t)| {
+            JamoDiff {
+                part: part,
+                expected: e,
+                typed: t,
+            }
+        })
+        .collect()
+}
+
+impl AnswerCheck {
+    /// Aligns the typed answer with the expected one syllable by syllable,
+    /// scoring each pair by how many of their jamo differ.
+    pub fn new(expected: &str, typed: &str) -> AnswerCheck {
+        let expected = normalize(expected);
+        let typed = normalize(typed);
+        let n = expected.len();
+        let m = typed.len();
+        let size = |ch: char| char_jamo(ch).len();
+        let substitution = |a: char, b: char| edit_distance(&char_jamo(a), &char_jamo(b));
+
+        let mut cost = vec![vec![0; m + 1]; n + 1];
+        for i in 1..n + 1 {
+            cost[i][0] = cost[i - 1][0] + size(expected[i - 1]);
+        }
+        for j in 1..m + 1 {
+            cost[0][j] = cost[0][j - 1] + size(typed[j - 1]);
+        }
+        for i in 1..n + 1 {
+            for j in 1..m + 1 {
+                let replace = cost[i - 1][j - 1] + substitution(expected[i - 1], typed[j - 1]);
+                let missing = cost[i - 1][j] + size(expected[i - 1]);
+                let extra = cost[i][j - 1] + size(typed[j - 1]);
+                cost[i][j] = replace.min(missing).min(extra);
+            }
+        }
+
+        let mut diffs = Vec::new();
+        let (mut i, mut j) = (n, m);
+        while i > 0 || j > 0 {
+            if i > 0 && j > 0 &&
+               cost[i][j] == cost[i - 1][j - 1] + substitution(expected[i - 1], typed[j - 1]) {
+                let (e, t) = (expected[i - 1], typed[j - 1]);
+                diffs.push(if e == t {
+                    SyllableDiff::Same(e)
+                } else {
+                    SyllableDiff::Changed {
+                        expected: e,
+                        typed: t,
+                        jamo: jamo_diffs(e, t),
+                    }
+                });
+                i -= 1;
+                j -= 1;
+            } else if i > 0 && cost[i][j] == cost[i - 1][j] + size(expected[i - 1]) {
+                diffs.push(SyllableDiff::Missing(expected[i - 1]));
+                i -= 1;
+            } else {
+                diffs.push(SyllableDiff::Extra(typed[j - 1]));
+                j -= 1;
+            }
+        }
+        diffs.reverse();
+
+        AnswerCheck {
+            diffs: diffs,
+            distance: cost[n][m],
+            n_jamo: cost[n][0],
+        }
+    }
+
+    #[inline]
+    pub fn is_correct(&self) -> bool {
+        self.distance == 0
+    }
+
+    /// Returns the share (0 to 1) of the expected jamo that were typed right.
+    pub fn credit(&self) -> f32 {
+        if self.n_jamo == 0 {
+            return if self.is_correct() { 1.0 } else { 0.0 };
+        }
+        1.0 - (self.distance.min(self.n_jamo) as f32 / self.n_jamo as f32)
+    }
+
+    /// Returns the grade to schedule the meaning with.
+    pub fn grade(&self) -> Grade {
+        match self.distance {
+            0 => Grade::Good,
+            1 => Grade::Hard,
+            _ => Grade::Again,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{AnswerCheck, SyllableDiff, JamoDiff};
+    use scheduler::Grade;
+
+    #[test]
+    fn correct() {
+        let check = AnswerCheck::new("감사합니다", " 감사합니다 ");
+        assert!(check.is_correct());
+        assert_eq!(Grade::Good, check.grade());
+    }
+
+    #[test]
+    fn batchim() {
+        let check = AnswerCheck::new("감사합니다", "감사함니다");
+        assert_eq!(1, check.distance);
+        assert_eq!(Grade::Hard, check.grade());
+        assert_eq!(SyllableDiff::Changed {
+                       expected: '합',
+                       typed: '함',
+                       jamo: vec![JamoDiff {
+                                      part: "batchim",
+                                      expected: Some('ㅂ'),
+                                      typed: Some('ㅁ'),
+                                  }],
+                   },
+                   check.diffs[2]);
+    }
+
+    #[test]
+    fn missing_syllable() {
+        let check = AnswerCheck::new("고맙습니다", "고맙니다");
+        assert_eq!(SyllableDiff::Missing('습'), check.diffs[2]);
+        assert_eq!(3, check.distance);
+        assert_eq!(Grade::Again, check.grade());
+    }
+}
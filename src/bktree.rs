@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use jamo::{to_jamo, edit_distance};
+
+struct Node {
+    word: String,
+    jamo: Vec<char>,
+    /// Children by their distance to this node.
+    children: BTreeMap<usize, Node>,
+}
+
+impl Node {
+    fn new(word: String, jamo: Vec<char>) -> Node {
+        Node {
+            word: word,
+            jamo: jamo,
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+/// A BK-tree of words, for finding the words within a given jamo edit
+/// distance of a query without comparing it to every word.
+/// See https://en.wikipedia.org/wiki/BK-tree
+pub struct BkTree {
+    root: Option<Node>,
+}
+
+impl BkTree {
+    #[inline]
+    pub fn new() -> BkTree {
+        BkTree { root: None }
+    }
+
+    pub fn insert(&mut self, word: String) {
+        let jamo = to_jamo(&word);
+        let mut node = match self.root {
+            Some(ref mut root) => root,
+            None => {
+                self.root = Some(Node::new(word, jamo));
+                return;
+            }
+        };
+        loop {
+            let distance = edit_distance(&node.jamo, &jamo);
+            if distance == 0 && node.word == word {
+                return;
+            }
+            let current = node;
+            match current.children.entry(distance) {
+                Entry::Occupied(entry) => node = entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(Node::new(word, jamo));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns the words at most `max_distance` jamo edits from the query,
+    /// along with their distance, closest first.
+    pub fn find(&self, query: &str, max_distance: usize) -> Vec<(usize, &str)> {
+        let query = to_jamo(query);
+        let mut found = Vec::new();
+        let mut stack = Vec::new();
+        if let Some(ref root) = self.root {
+            stack.push(root);
+        }
+        while let Some(node) = stack.pop() {
+            let distance = edit_distance(&node.jamo, &query);
+            if distance <= max_distance {
+                found.push((distance, node.word.as_str()));
+            }
+            // By the triangle inequality, only children in this range can match
+            let min = distance.saturating_sub(max_distance);
+            for (_, child) in node.children.range(min..distance + max_distance + 1) {
+                stack.push(child);
+            }
+        }
+        found.sort();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::BkTree;
+
+    fn tree() -> BkTree {
+        let mut tree = BkTree::new();
+        for word in &["괜찮다", "감사", "감사합니다", "학생", "학교", "괜히"] {
+            tree.insert(word.to_string());
+        }
+        tree
+    }
+
+    #[test]
+    fn exact() {
+        assert_eq!(vec![(0, "학생")], tree().find("학생", 0));
+    }
+
+    #[test]
+    fn closest() {
+        let tree = tree();
+        assert_eq!(vec![(1, "괜찮다")], tree.find("괜찬다", 1));
+        assert_eq!(vec![(1, "학교"), (3, "학생")], tree.find("학겨", 3));
+    }
+}
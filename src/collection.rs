@@ -1,29 +1,132 @@
 use std::collections::HashMap;
+use std::collections::hash_map;
 use source::Meaning;
-use serde_json;
+use scheduler::{ReviewState, Grade};
+use migrate::{self, MigrateError, COLLECTION_VERSION};
+use jamo::{is_hangul, matches_initial};
+use serde_json::{self, Value};
+use error::Error;
+use date;
+use save;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// How a source changed a meaning.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceAction {
+    Add,
+    /// The source had the meaning that was already in the collection.
+    Confirm,
+    /// The meaning was replaced by the text from the source.
+    Replace,
+    /// The meaning was rewritten when the source conflicted with it.
+    Update,
+}
+
+/// A change made to a meaning by a source.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// The id of the source title.
+    pub source: u32,
+    pub lineno: Option<usize>,
+    pub action: SourceAction,
+    /// Seconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+}
+
+impl Provenance {
+    fn new(source: u32, lineno: Option<usize>, action: SourceAction) -> Provenance {
+        Provenance {
+            source: source,
+            lineno: lineno,
+            action: action,
+            timestamp: Some(date::now()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ColMeaning {
     pub text: String,
     pub symbol: Option<String>,
+    /// The source that last added or replaced the meaning.
     pub source: u32,
+    /// Every change made to the meaning, oldest first.
+    pub provenance: Vec<Provenance>,
+    #[serde(default)]
+    pub example: Option<String>,
+    #[serde(default)]
+    pub review: ReviewState,
+    /// The meaning as it was before it was last replaced.
+    #[serde(default)]
+    pub previous: Option<Box<ColMeaning>>,
 }
 impl ColMeaning {
-    fn new(meaning: Meaning, source: u32) -> ColMeaning {
+    fn new(meaning: Meaning, source: u32, lineno: Option<usize>) -> ColMeaning {
         ColMeaning {
             text: meaning.text,
             symbol: meaning.symbol,
             source: source,
+            provenance: vec![Provenance::new(source, lineno, SourceAction::Add)],
+            example: meaning.example,
+            review: ReviewState::default(),
+            previous: None,
         }
     }
+
+    /// Merges a meaning with the same text into this one.
+    fn absorb(&mut self, other: ColMeaning) {
+        for change in other.provenance {
+            if !self.provenance.contains(&change) {
+                self.provenance.push(change);
+            }
+        }
+        // Undated changes are the oldest
+        self.provenance.sort_by_key(|change| change.timestamp);
+        if other.review.is_ahead_of(&self.review) {
+            self.review = other.review;
+        }
+        if self.previous.is_none() {
+            self.previous = other.previous;
+        }
+    }
+
+    /// Undoes the changes a source made to the meaning, returning None if
+    /// the source added it. The texts of the undone versions are added to
+    /// `undone`.
+    fn without_source(mut self, source: u32, undone: &mut Vec<String>) -> Option<ColMeaning> {
+        while self.source == source {
+            undone.push(self.text.clone());
+            match self.previous.take() {
+                Some(previous) => self = *previous,
+                None => return None,
+            }
+        }
+        if let Some(previous) = self.previous.take() {
+            self.previous = previous.without_source(source, undone).map(Box::new);
+        }
+        self.provenance.retain(|p| p.source != source);
+        Some(self)
+    }
+}
+
+/// What removing the meanings imported from a source did.
+#[derive(Debug, Default)]
+pub struct Unimport {
+    /// The term and text of every version of a meaning the source made.
+    pub undone: Vec<(String, String)>,
+    /// The meanings restored to what they were before the source replaced
+    /// them, as (term, restored text).
+    pub restored: Vec<(String, String)>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Collection {
+    #[serde(default)]
+    version: u32,
     contents: HashMap<String, Vec<ColMeaning>>,
     titles: HashMap<u32, String>,
     titles_rev: HashMap<String, u32>,
     next_title_id: u32,
+    #[serde(skip_serializing, skip_deserializing)]
     empty: Vec<ColMeaning>,
 }
 
@@ -31,6 +134,7 @@ impl Collection {
     #[inline]
     pub fn new() -> Collection {
         Collection {
+            version: COLLECTION_VERSION,
             contents: HashMap::new(),
             titles: HashMap::new(),
             titles_rev: HashMap::new(),
@@ -39,8 +143,32 @@ impl Collection {
         }
     }
 
-    pub fn from_json(json: &str) -> serde_json::Result<Collection> {
-        serde_json::from_str(json)
+    /// Reads a collection, upgrading it if it was saved with an older schema.
+    pub fn from_json(json: &str) -> Result<Collection, MigrateError> {
+        Collection::migrate_json(json).map(|(collection, _)| collection)
+    }
+
+    /// Reads a collection of any schema version, and returns it along with
+    /// the version it was saved with.
+    pub fn migrate_json(json: &str) -> Result<(Collection, u32), MigrateError> {
+        let value: Value = serde_json::from_str(json)?;
+        let (value, version) = migrate::migrate_collection(value)?;
+        let mut collection: Collection = serde_json::from_value(value)?;
+        collection.version = COLLECTION_VERSION;
+        Ok((collection, version))
+    }
+
+    /// Loads a collection, or returns an empty one if the file doesn't exist.
+    pub fn load(path: &str) -> Result<Collection, Error> {
+        match save::read(path)? {
+            Some(json) => Collection::from_json(&json).map_err(Error::Parse),
+            None => Ok(Collection::new()),
+        }
+    }
+
+    /// Saves the collection. If `backup` is set, the previous version is kept.
+    pub fn save(&self, path: &str, backup: bool) -> Result<(), Error> {
+        save::write_json(self, path, backup)
     }
 
     fn ensure_title(&mut self, title: &str) -> u32 {
@@ -59,18 +187,51 @@ impl Collection {
         self.titles.get(&id)
     }
 
-    pub fn add_meaning(&mut self, term: String, meaning: Meaning, source_title: &str) {
+    pub fn add_meaning(&mut self,
+                       term: String,
+                       meaning: Meaning,
+                       source_title: &str,
+                       lineno: Option<usize>) {
         let id = self.ensure_title(source_title);
-        let meaning = ColMeaning::new(meaning, id);
+        let meaning = ColMeaning::new(meaning, id, lineno);
         self.contents.entry(term).or_insert(Vec::new()).push(meaning);
     }
 
+    /// Records that a source has a meaning that is already in the collection,
+    /// unless that is already known. Returns whether it was recorded.
+    pub fn confirm_meaning(&mut self,
+                           term: &str,
+                           text: &str,
+                           source_title: &str,
+                           lineno: Option<usize>)
+                           -> bool {
+        let is_new = match self.titles_rev.get(source_title) {
+            Some(&id) => {
+                self.meanings(term)
+                    .iter()
+                    .any(|m| m.text == text && !m.provenance.iter().any(|p| p.source == id))
+            }
+            None => self.contains(term, text),
+        };
+        if !is_new {
+            return false;
+        }
+        let id = self.ensure_title(source_title);
+        let meanings = self.contents.get_mut(term).unwrap();
+        for meaning in meanings.iter_mut().filter(|m| m.text == text) {
+            meaning.provenance.push(Provenance::new(id, lineno, SourceAction::Confirm));
+        }
+        true
+    }
+
     pub fn replace_meaning(&mut self,
                            term: &str,
                            index: usize,
                            text: String,
                            symbol: Option<String>,
-                           source_title: &str)
+                           source_title: &str,
+                           lineno: Option<usize>,
+                           action: SourceAction)
                            -> Result<(), String> {
         let n_meanings = self.contents.get(term).map(|m| m.len()).unwrap_or(0);
         if n_meanings == 0 {
@@ -81,21 +242,220 @@ impl Collection {
             let id = self.ensure_title(source_title);
             let ref mut meanings = self.contents.get_mut(term).unwrap();
             let ref mut colmeaning = meanings[index];
+            // Move the older versions into the copy instead of cloning them
+            let older = colmeaning.previous.take();
+            let mut before = colmeaning.clone();
+            before.previous = older;
+            colmeaning.previous = Some(Box::new(before));
+            if colmeaning.text != text {
+                colmeaning.review = ReviewState::default();
+            }
             colmeaning.text = text;
             if colmeaning.symbol.is_none() {
                 colmeaning.symbol = symbol;
             }
             colmeaning.source = id;
+            colmeaning.provenance.push(Provenance::new(id, lineno, action));
             Ok(())
         }
     }
 
+    /// Removes every meaning the given source added and reverts the ones it
+    /// replaced, then forgets the source title.
+    pub fn unimport(&mut self, source_title: &str) -> Result<Unimport, String> {
+        let id = match self.titles_rev.remove(source_title) {
+            Some(id) => id,
+            None => return Err(format!("No source with the title '{}' found", source_title)),
+        };
+        self.titles.remove(&id);
+        let mut unimport = Unimport::default();
+        for (term, meanings) in &mut self.contents {
+            let mut kept = Vec::with_capacity(meanings.len());
+            for meaning in meanings.drain(..) {
+                let replaced_by_source = meaning.source == id;
+                let mut undone = Vec::new();
+                if let Some(meaning) = meaning.without_source(id, &mut undone) {
+                    if replaced_by_source {
+                        unimport.restored.push((term.clone(), meaning.text.clone()));
+                    }
+                    kept.push(meaning);
+                }
+                unimport.undone.extend(undone.into_iter().map(|text| (term.clone(), text)));
+            }
+            *meanings = kept;
+        }
+        self.contents.retain(|_, meanings| !meanings.is_empty());
+        Ok(unimport)
+    }
+
+    /// Removes a meaning, and the term too if it has no meanings left.
+    pub fn remove_meaning(&mut self, term: &str, index: usize) -> Result<ColMeaning, String> {
+        let n_meanings = self.meanings(term).len();
+        if n_meanings == 0 {
+            return Err(format!("No meanings found for term '{}'", term));
+        } else if index >= n_meanings {
+            return Err(format!("Invalid index: {} >= {}.", index, n_meanings));
+        }
+        let removed = self.contents.get_mut(term).unwrap().remove(index);
+        if n_meanings == 1 {
+            self.contents.remove(term);
+        }
+        Ok(removed)
+    }
+
+    /// Moves the meanings of a term to a new term.
+    pub fn rename_term(&mut self, term: &str, new_term: String) -> Result<(), String> {
+        if self.contents.contains_key(&new_term) {
+            return Err(format!("The term '{}' already exists, merge the terms instead",
+                               new_term));
+        }
+        match self.contents.remove(term) {
+            Some(meanings) => {
+                self.contents.insert(new_term, meanings);
+                Ok(())
+            }
+            None => Err(format!("No meanings found for term '{}'", term)),
+        }
+    }
+
+    /// Moves the meanings of a term into another term. A meaning the other
+    /// term already has is merged into it instead: it gets the provenance of
+    /// both, the review state that is further along and the older versions
+    /// of the moved meaning if it has none. Returns how many meanings were
+    /// moved.
+    pub fn merge_terms(&mut self, term: &str, into: &str) -> Result<usize, String> {
+        if term == into {
+            return Err(format!("Cannot merge '{}' into itself", term));
+        } else if !self.contents.contains_key(into) {
+            return Err(format!("No meanings found for term '{}', rename the term instead",
+                               into));
+        }
+        let meanings = match self.contents.remove(term) {
+            Some(meanings) => meanings,
+            None => return Err(format!("No meanings found for term '{}'", term)),
+        };
+        let ref mut existing = self.contents.get_mut(into).unwrap();
+        let mut n_moved = 0;
+        for meaning in meanings {
+            match existing.iter().position(|m| m.text == meaning.text) {
+                Some(index) => existing[index].absorb(meaning),
+                None => {
+                    existing.push(meaning);
+                    n_moved += 1;
+                }
+            }
+        }
+        Ok(n_moved)
+    }
+
+    /// Returns the term and index of every meaning due for review on the
+    /// given day, the most overdue first, followed by new meanings. Only
+    /// `new_per_day` meanings are reviewed for the first time on a day, so
+    /// the rest of the new meanings wait for the next days.
+    pub fn due(&self, today: u32, new_per_day: usize) -> Vec<(String, usize)> {
+        let mut due = Vec::new();
+        let mut new = Vec::new();
+        let mut n_introduced = 0;
+        for (term, meanings) in &self.contents {
+            for (index, meaning) in meanings.iter().enumerate() {
+                let review = &meaning.review;
+                if review.is_new() {
+                    new.push((term.clone(), index));
+                    continue;
+                }
+                if review.first_review == Some(today) {
+                    n_introduced += 1;
+                }
+                if review.is_due(today) {
+                    due.push((review.due, term.clone(), index));
+                }
+            }
+        }
+        due.sort();
+        new.sort();
+        new.truncate(new_per_day.saturating_sub(n_introduced));
+        let mut due = due.into_iter().map(|(_, term, index)| (term, index)).collect::<Vec<_>>();
+        due.extend(new);
+        due
+    }
+
+    /// Grades a review of the given meaning and schedules the next one.
+    pub fn review(&mut self,
+                  term: &str,
+                  index: usize,
+                  grade: Grade,
+                  today: u32)
+                  -> Result<(), String> {
+        match self.contents.get_mut(term).and_then(|m| m.get_mut(index)) {
+            Some(meaning) => {
+                meaning.review.review(grade, today);
+                Ok(())
+            }
+            None => Err(format!("No meaning {} found for term '{}'", index, term)),
+        }
+    }
+
+    /// Finds the terms matching a search by initial consonants (초성), like
+    /// 'ㄱㅅ' for '감사'. Whole syllables in the query must match exactly, so
+    /// '가ㅅ' finds '가상' but not '감사'. Anything but Hangul is ignored, such
+    /// as the '~' and parentheses of grammar patterns. Terms starting with the
+    /// query come first, then the ones containing it, shortest first.
+    pub fn search_initials(&self, query: &str) -> Vec<&String> {
+        let query = query.chars().filter(|&c| is_hangul(c)).collect::<Vec<_>>();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut found = Vec::new();
+        for term in self.contents.keys() {
+            let chars = term.chars().filter(|&c| is_hangul(c)).collect::<Vec<_>>();
+            let position = chars.windows(query.len()).position(|window| {
+                window.iter().zip(&query).all(|(&ch, &q)| matches_initial(q, ch))
+            });
+            if let Some(position) = position {
+                found.push((position > 0, chars.len(), term));
+            }
+        }
+        found.sort();
+        found.into_iter().map(|(_, _, term)| term).collect()
+    }
+
     /// Returns whether a definition for the given term is contained.
     #[inline]
     pub fn contains(&self, term: &str, meaning: &str) -> bool {
         self.contents.get(term).map(|m| m.iter().any(|cm| &cm.text == meaning)).unwrap_or(false)
     }
 
+    /// Iterates over the terms and their meanings.
+    #[inline]
+    pub fn iter(&self) -> hash_map::Iter<String, Vec<ColMeaning>> {
+        self.contents.iter()
+    }
+
+    /// Returns the index of the meaning of a term that came from the given
+    /// line of a source, if there is one.
+    pub fn meaning_from_line(&self, term: &str, source_title: &str, lineno: usize) -> Option<usize> {
+        let id = match self.titles_rev.get(source_title) {
+            Some(&id) => id,
+            None => return None,
+        };
+        self.meanings(term)
+            .iter()
+            .position(|m| m.provenance.iter().any(|p| p.source == id && p.lineno == Some(lineno)))
+    }
+
+    /// Replaces all meanings of a term, or removes the term if `meanings` is
+    /// None.
+    pub fn set_meanings(&mut self, term: &str, meanings: Option<Vec<ColMeaning>>) {
+        match meanings {
+            Some(meanings) => {
+                self.contents.insert(term.to_string(), meanings);
+            }
+            None => {
+                self.contents.remove(term);
+            }
+        }
+    }
+
     /// Returns the meanings associated with the given term.
     #[inline]
     pub fn meanings(&self, term: &str) -> &Vec<ColMeaning> {
@@ -106,3 +466,119 @@ impl Collection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use self::super::{Collection, SourceAction};
+    use scheduler::Grade;
+    use testing::{collection, meaning};
+
+    #[test]
+    fn remove() {
+        let mut col = collection(&["감사", "학생"]);
+        assert_eq!("-", col.remove_meaning("감사", 0).unwrap().text);
+        assert!(col.meanings("감사").is_empty());
+        assert!(col.remove_meaning("학생", 1).is_err());
+    }
+
+    #[test]
+    fn rename_and_merge() {
+        let mut col = collection(&["감사", "감사합니다", "학생"]);
+        assert!(col.rename_term("감사", "학생".to_string()).is_err());
+        col.rename_term("감사", "감사하다".to_string()).unwrap();
+        assert_eq!(1, col.meanings("감사하다").len());
+        assert_eq!(Ok(0), col.merge_terms("감사하다", "감사합니다"));
+        assert!(col.meanings("감사하다").is_empty());
+        assert_eq!(1, col.meanings("감사합니다").len());
+    }
+
+    #[test]
+    fn unimport() {
+        let mut col = collection(&["감사", "학생"]);
+        col.add_meaning("학생".to_string(), meaning("elev"), "lesson 2", Some(3));
+        col.replace_meaning("감사", 0, "tak".to_string(), None, "lesson 2", Some(4), SourceAction::Replace)
+            .unwrap();
+        let unimport = col.unimport("lesson 2").unwrap();
+        assert_eq!(2, unimport.undone.len());
+        assert_eq!(vec![("감사".to_string(), "-".to_string())], unimport.restored);
+        assert_eq!("-", col.meanings("감사")[0].text);
+        assert_eq!(1, col.meanings("감사")[0].provenance.len());
+        assert_eq!(1, col.meanings("학생").len());
+        assert!(col.unimport("lesson 2").is_err());
+    }
+
+    #[test]
+    fn from_line() {
+        let mut col = collection(&["학생"]);
+        col.add_meaning("학생".to_string(), meaning("elev"), "Lesson 3", Some(7));
+        assert_eq!(Some(1), col.meaning_from_line("학생", "Lesson 3", 7));
+        assert_eq!(None, col.meaning_from_line("학생", "Lesson 3", 8));
+        assert_eq!(None, col.meaning_from_line("학생", "test", 7));
+    }
+
+    #[test]
+    fn provenance() {
+        let mut col = collection(&["감사"]);
+        assert!(col.confirm_meaning("감사", "-", "lesson 2", Some(1)));
+        assert!(!col.confirm_meaning("감사", "-", "lesson 2", Some(1)));
+        assert!(!col.confirm_meaning("감사", "-", "test", None));
+        assert!(!col.confirm_meaning("감사", "tak", "lesson 3", None));
+        let actions = col.meanings("감사")[0].provenance.iter().map(|p| p.action).collect::<Vec<_>>();
+        assert_eq!(vec![SourceAction::Add, SourceAction::Confirm], actions);
+    }
+
+    #[test]
+    fn initials() {
+        let col = collection(&["감사", "감사합니다", "가상", "고맙다", "무감소"]);
+        assert_eq!(vec!["가상", "감사", "감사합니다", "무감소"], col.search_initials("ㄱㅅ"));
+        assert_eq!(vec!["가상"], col.search_initials("가ㅅ"));
+    }
+
+    #[test]
+    fn grammar_patterns() {
+        let col = collection(&["A~(으)ㄴ가요", "AV~(으)ㄴ/는/(으)ㄹ 데"]);
+        assert_eq!(vec!["A~(으)ㄴ가요"], col.search_initials("ㄴㄱㅇ"));
+        assert_eq!(vec!["AV~(으)ㄴ/는/(으)ㄹ 데"], col.search_initials("ㄴㄴ"));
+    }
+
+    #[test]
+    fn merge_duplicates() {
+        let mut col = Collection::new();
+        col.add_meaning("감사".to_string(), meaning("thanks"), "One", Some(2));
+        col.add_meaning("감사합니다".to_string(), meaning("thanks"), "Two", Some(3));
+        col.add_meaning("감사합니다".to_string(), meaning("thank you"), "Two", Some(4));
+        col.review("감사", 0, Grade::Good, 10).unwrap();
+        assert_eq!(Ok(0), col.merge_terms("감사", "감사합니다"));
+        let merged = &col.meanings("감사합니다")[0];
+        let mut lines = merged.provenance.iter().map(|p| p.lineno).collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(vec![Some(2), Some(3)], lines);
+        assert_eq!(11, merged.review.due);
+        assert_eq!(2, col.meanings("감사합니다").len());
+    }
+
+    #[test]
+    fn new_per_day() {
+        let mut col = collection(&["감사", "사과", "학생"]);
+        col.review("학생", 0, Grade::Good, 10).unwrap();
+        assert_eq!(vec![("감사".to_string(), 0)], col.due(10, 2));
+        assert_eq!(vec![("학생".to_string(), 0), ("감사".to_string(), 0)],
+                   col.due(11, 1));
+    }
+
+    #[test]
+    fn replace_chain() {
+        let mut col = collection(&["감사"]);
+        for n in 1..5 {
+            col.replace_meaning("감사", 0, n.to_string(), None, "test", None, SourceAction::Update)
+                .unwrap();
+        }
+        let mut texts = Vec::new();
+        let mut meaning = Some(&col.meanings("감사")[0]);
+        while let Some(m) = meaning {
+            texts.push(m.text.clone());
+            meaning = m.previous.as_ref().map(|p| &**p);
+        }
+        assert_eq!(vec!["4", "3", "2", "1", "-"], texts);
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use toml;
+
+/// The files a command works on.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Profile {
+    pub collection: Option<String>,
+    pub history: Option<String>,
+}
+
+/// How the term of a conflicting note is looked up.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DictionarySettings {
+    /// A command run for every conflict, where '{term}' is replaced by the
+    /// term. It is split into words before that, so the term stays one
+    /// argument.
+    pub command: Option<String>,
+    /// Whether to wait for the command before asking, for dictionaries that
+    /// print to the terminal.
+    #[serde(default)]
+    pub wait: bool,
+    /// A local dictionary file, whose definitions are shown with the conflict.
+    pub file: Option<String>,
+}
+
+/// The user's settings. The top-level files are used when no profile is
+/// chosen, and by profiles that leave them out.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    pub collection: Option<String>,
+    pub history: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    pub dictionary: DictionarySettings,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Read(ref err) => write!(f, "could not read the file: {}", err),
+            ConfigError::Parse(ref err) => write!(f, "could not parse the file: {}", err),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn description(&self) -> &str {
+        match *self {
+            ConfigError::Read(_) => "could not read the file",
+            ConfigError::Parse(_) => "could not parse the file",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ConfigError::Read(ref err) => Some(err),
+            ConfigError::Parse(ref err) => Some(err),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_toml(text: &str) -> Result<Config, ConfigError> {
+        toml::from_str(text).map_err(ConfigError::Parse)
+    }
+
+    /// Loads a config file, or returns None if it doesn't exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Config>, ConfigError> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(ConfigError::Read(err)),
+        };
+        let mut text = String::new();
+        file.read_to_string(&mut text).map_err(ConfigError::Read)?;
+        Config::from_toml(&text).map(Some)
+    }
+
+    /// Returns '$XDG_CONFIG_HOME/memori/config.toml', or
+    /// '~/.config/memori/config.toml' if that isn't set.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                match env::home_dir() {
+                    Some(home) => home.join(".config"),
+                    None => return None,
+                }
+            }
+        };
+        Some(dir.join("memori").join("config.toml"))
+    }
+
+    /// Returns the files of a profile, or the top-level ones if no profile
+    /// is given. '~/' at the start of a path is the home directory.
+    pub fn profile(&self, name: Option<&str>) -> Option<Profile> {
+        let profile = match name {
+            Some(name) => {
+                match self.profiles.get(name) {
+                    Some(profile) => profile.clone(),
+                    None => return None,
+                }
+            }
+            None => Profile::default(),
+        };
+        Some(Profile {
+            collection: profile.collection.or(self.collection.clone()).map(|p| expand_home(&p)),
+            history: profile.history.or(self.history.clone()).map(|p| expand_home(&p)),
+        })
+    }
+
+    /// Returns the path of the local dictionary, if one is set.
+    pub fn dictionary_file(&self) -> Option<String> {
+        self.dictionary.file.as_ref().map(|p| expand_home(p))
+    }
+}
+
+fn expand_home(path: &str) -> String {
+    if path.starts_with("~/") {
+        if let Some(home) = env::home_dir() {
+            return home.join(&path[2..]).to_string_lossy().to_string();
+        }
+    }
+    path.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{Config, Profile};
+
+    #[test]
+    fn profiles() {
+        let config = Config::from_toml(r#"
+collection = "korean.json"
+history = "korean-history.json"
+
+[profiles.test]
+collection = "test.json"
+"#)
+            .unwrap();
+        assert_eq!(Some(Profile {
+                       collection: Some("korean.json".to_string()),
+                       history: Some("korean-history.json".to_string()),
+                   }),
+                   config.profile(None));
+        assert_eq!(Some(Profile {
+                       collection: Some("test.json".to_string()),
+                       history: Some("korean-history.json".to_string()),
+                   }),
+                   config.profile(Some("test")));
+        assert_eq!(None, config.profile(Some("other")));
+        assert_eq!(None, config.dictionary.command);
+    }
+
+    #[test]
+    fn dictionary() {
+        let config = Config::from_toml(r#"
+[dictionary]
+command = "sdcv -n {term}"
+wait = true
+file = "dictionary.tsv"
+"#)
+            .unwrap();
+        assert_eq!(Some("sdcv -n {term}".to_string()), config.dictionary.command);
+        assert!(config.dictionary.wait);
+        assert_eq!(Some("dictionary.tsv".to_string()), config.dictionary_file());
+    }
+}
@@ -0,0 +1,105 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Returns the current time as seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Returns the current (UTC) day as the number of days since the Unix epoch.
+pub fn today() -> u32 {
+    day_of(now())
+}
+
+/// Returns the (UTC) day of a timestamp.
+pub fn day_of(timestamp: u64) -> u32 {
+    (timestamp / SECONDS_PER_DAY) as u32
+}
+
+/// Formats a day number as 'YYYY-MM-DD'.
+pub fn format_day(day: u32) -> String {
+    let (year, month, day) = civil_from_days(day as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Parses a 'YYYY-MM-DD' date as a day number.
+pub fn parse_day(text: &str) -> Option<u32> {
+    let mut parts = text.split('-').map(|p| p.parse::<u32>().ok());
+    let (year, month, day) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(year)), Some(Some(month)), Some(Some(day)), None) => (year as i64, month, day),
+        _ => return None,
+    };
+    let days = days_from_civil(year, month, day);
+    // Catches days like 02-30, which would roll over into the next month
+    if days < 0 || civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    Some(days as u32)
+}
+
+/// Formats a timestamp (seconds since the epoch) as 'YYYY-MM-DD HH:MM' UTC.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let seconds = timestamp % SECONDS_PER_DAY;
+    format!("{} {:02}:{:02}",
+            format_day(day_of(timestamp)),
+            seconds / 3600,
+            (seconds % 3600) / 60)
+}
+
+/// Converts days since the epoch to a (year, month, day) date.
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) date to days since the epoch.
+/// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{format_day, format_timestamp, parse_day};
+
+    #[test]
+    fn epoch() {
+        assert_eq!("1970-01-01", format_day(0));
+    }
+
+    #[test]
+    fn leap_day() {
+        assert_eq!("2016-02-29", format_day(16860));
+        assert_eq!("2016-03-01", format_day(16861));
+    }
+
+    #[test]
+    fn timestamp() {
+        assert_eq!("2017-03-04 05:06", format_timestamp(1488603960));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Some(0), parse_day("1970-01-01"));
+        assert_eq!(Some(16860), parse_day("2016-02-29"));
+        assert_eq!(None, parse_day("2017-02-29"));
+        assert_eq!(None, parse_day("2017-13-01"));
+        assert_eq!(None, parse_day("2017-03"));
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::mem;
+use serde_json::{self, Value};
+use xml::reader::{self, EventReader, XmlEvent};
+use error::Error;
+use migrate::{self, MigrateError, DICTIONARY_VERSION};
+use save;
+
+/// A meaning of a dictionary word.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sense {
+    /// The definition, in the language of the dictionary.
+    pub definition: String,
+    /// An English equivalent, if the dictionary has one.
+    pub translation: Option<String>,
+}
+
+impl fmt::Display for Sense {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.translation {
+            Some(ref translation) => write!(f, "{}: {}", translation, self.definition),
+            None => write!(f, "{}", self.definition),
+        }
+    }
+}
+
+/// A word in a dictionary.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The Chinese characters of a Sino-Korean word.
+    pub hanja: Option<String>,
+    /// The part of speech, as the dictionary names it.
+    pub pos: Option<String>,
+    pub senses: Vec<Sense>,
+}
+
+impl Entry {
+    /// Returns the term with the hanja and part of speech of the entry, like
+    /// '학생 (學生) 명사'.
+    pub fn heading(&self, term: &str) -> String {
+        let mut heading = term.to_string();
+        if let Some(ref hanja) = self.hanja {
+            heading.push_str(&format!(" ({})", hanja));
+        }
+        if let Some(ref pos) = self.pos {
+            heading.push_str(&format!(" {}", pos));
+        }
+        heading
+    }
+}
+
+/// The dumps that can be imported into a dictionary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// The XML (LMF) export of the Basic Korean Dictionary (한국어기초사전).
+    /// English equivalents are kept as translations.
+    Krdict,
+    /// The tab-separated kengdic file, with the columns id, surface, hanja
+    /// and gloss, optionally below a header naming them.
+    Kengdic,
+}
+
+impl DumpFormat {
+    pub fn from_str(text: &str) -> Option<DumpFormat> {
+        match text {
+            "krdict" => Some(DumpFormat::Krdict),
+            "kengdic" => Some(DumpFormat::Kengdic),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of a dump from its extension.
+    pub fn guess(path: &str) -> Option<DumpFormat> {
+        let path = path.to_lowercase();
+        if path.ends_with(".xml") {
+            Some(DumpFormat::Krdict)
+        } else if path.ends_with(".tsv") || path.ends_with(".txt") {
+            Some(DumpFormat::Kengdic)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    Xml(reader::Error),
+}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> ImportError {
+        ImportError::Io(err)
+    }
+}
+
+impl From<reader::Error> for ImportError {
+    fn from(err: reader::Error) -> ImportError {
+        ImportError::Xml(err)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImportError::Io(ref err) => write!(f, "could not read the dump: {}", err),
+            ImportError::Xml(ref err) => write!(f, "invalid XML: {}", err),
+        }
+    }
+}
+
+impl error::Error for ImportError {
+    fn description(&self) -> &str {
+        match *self {
+            ImportError::Io(_) => "could not read the dump",
+            ImportError::Xml(_) => "invalid XML",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ImportError::Io(ref err) => Some(err),
+            ImportError::Xml(ref err) => Some(err),
+        }
+    }
+}
+
+/// Words from a local dictionary, shown while deciding conflicts. It is
+/// either a plain list of definitions, or an index imported from dictionary
+/// dumps.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dictionary {
+    #[serde(default)]
+    version: u32,
+    entries: HashMap<String, Vec<Entry>>,
+}
+
+impl Default for Dictionary {
+    fn default() -> Dictionary {
+        Dictionary {
+            version: DICTIONARY_VERSION,
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl Dictionary {
+    /// Reads a dictionary with a definition per line:
+    /// `<term> TAB <definition>`. A term can have several lines. Empty
+    /// lines, lines starting with '#' and lines without a tab are skipped.
+    pub fn parse(text: &str) -> Dictionary {
+        let mut dictionary = Dictionary::default();
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let (term, definition) = match line.find('\t') {
+                Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+                None => continue,
+            };
+            if term.is_empty() || definition.is_empty() {
+                continue;
+            }
+            dictionary.insert(term,
+                              Entry {
+                                  hanja: None,
+                                  pos: None,
+                                  senses: vec![Sense {
+                                                   definition: definition.to_string(),
+                                                   translation: None,
+                                               }],
+                              });
+        }
+        dictionary
+    }
+
+    /// Returns whether the contents of a dictionary file are an index
+    /// written by `save`, rather than a list of definitions.
+    pub fn is_index(text: &str) -> bool {
+        text.trim_left().starts_with('{')
+    }
+
+    /// Reads a dictionary file of either kind. Indexes saved with an older
+    /// schema are migrated.
+    pub fn from_text(text: &str) -> Result<Dictionary, MigrateError> {
+        if !Dictionary::is_index(text) {
+            return Ok(Dictionary::parse(text));
+        }
+        let value: Value = serde_json::from_str(text)?;
+        let (value, _) = migrate::migrate_dictionary(value)?;
+        let mut dictionary: Dictionary = serde_json::from_value(value)?;
+        dictionary.version = DICTIONARY_VERSION;
+        Ok(dictionary)
+    }
+
+    pub fn load(path: &str) -> Result<Dictionary, Error> {
+        match save::read(path)? {
+            Some(text) => Dictionary::from_text(&text).map_err(Error::Parse),
+            None => {
+                let err = io::Error::new(io::ErrorKind::NotFound, "the file does not exist");
+                Err(Error::Open(err))
+            }
+        }
+    }
+
+    /// Saves the dictionary as an index, keeping the previous versions.
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        save::write_json(self, path, true)
+    }
+
+    /// Returns the number of terms in the dictionary.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the entries of a term, in the order they were added.
+    pub fn lookup(&self, term: &str) -> &[Entry] {
+        self.entries.get(term).map(|e| &e[..]).unwrap_or(&[])
+    }
+
+    /// Adds an entry for a term. An entry with the same hanja and part of
+    /// speech gets the new senses instead, so that importing a dump twice
+    /// changes nothing.
+    pub fn insert(&mut self, term: &str, entry: Entry) {
+        let entries = self.entries.entry(term.to_string()).or_insert(Vec::new());
+        if let Some(existing) = entries.iter_mut()
+            .find(|e| e.hanja == entry.hanja && e.pos == entry.pos) {
+            for sense in entry.senses {
+                if !existing.senses.contains(&sense) {
+                    existing.senses.push(sense);
+                }
+            }
+            return;
+        }
+        entries.push(entry);
+    }
+
+    /// Adds the words of a dump, and returns how many entries it had.
+    pub fn import(&mut self, path: &str, format: DumpFormat) -> Result<usize, ImportError> {
+        // The XML parser reads a byte at a time
+        let file = BufReader::new(File::open(path)?);
+        match format {
+            DumpFormat::Krdict => self.import_krdict(file),
+            DumpFormat::Kengdic => self.import_kengdic(file),
+        }
+    }
+
+    /// Adds the entries of a KRDict LMF export: `LexicalEntry` elements with
+    /// `feat` elements for the form (in `Lemma`), part of speech and origin,
+    /// and `Sense` elements with a definition and `Equivalent`s in other
+    /// languages.
+    pub fn import_krdict<R: Read>(&mut self, reader: R) -> Result<usize, ImportError> {
+        let mut n_entries = 0;
+        // The elements the parser is in
+        let mut path: Vec<String> = Vec::new();
+        let mut term: Option<String> = None;
+        let mut entry = new_entry();
+        // The equivalent being read: (language, lemma)
+        let mut equivalent: (Option<String>, Option<String>) = (None, None);
+        for event in EventReader::new(reader) {
+            match event? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    if name.local_name == "feat" {
+                        let attribute = |key| {
+                            attributes.iter()
+                                .find(|a| a.name.local_name == key)
+                                .map(|a| a.value.trim().to_string())
+                        };
+                        if let (Some(att), Some(val)) = (attribute("att"), attribute("val")) {
+                            let parent = path.last().map_or("", |p| p.as_str());
+                            match (parent, att.as_str()) {
+                                ("Lemma", "writtenForm") => term = Some(val),
+                                ("LexicalEntry", "partOfSpeech") => entry.pos = Some(val),
+                                ("LexicalEntry", "origin") if has_hanja(&val) => {
+                                    entry.hanja = Some(val)
+                                }
+                                ("Sense", "definition") => {
+                                    entry.senses.push(Sense {
+                                        definition: val,
+                                        translation: None,
+                                    })
+                                }
+                                ("Equivalent", "language") => equivalent.0 = Some(val),
+                                ("Equivalent", "lemma") => equivalent.1 = Some(val),
+                                _ => {}
+                            }
+                        }
+                    } else if name.local_name == "LexicalEntry" {
+                        term = None;
+                        entry = new_entry();
+                    } else if name.local_name == "Equivalent" {
+                        equivalent = (None, None);
+                    }
+                    path.push(name.local_name);
+                }
+                XmlEvent::EndElement { name } => {
+                    path.pop();
+                    if name.local_name == "Equivalent" {
+                        if let (Some("영어"), Some(lemma)) =
+                               (equivalent.0.as_ref().map(|l| l.as_str()), equivalent.1.take()) {
+                            if let Some(sense) = entry.senses.last_mut() {
+                                if sense.translation.is_none() {
+                                    sense.translation = Some(lemma);
+                                }
+                            }
+                        }
+                    } else if name.local_name == "LexicalEntry" {
+                        if let Some(term) = term.take() {
+                            let entry = mem::replace(&mut entry, new_entry());
+                            if !entry.senses.is_empty() {
+                                self.insert(&term, entry);
+                                n_entries += 1;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(n_entries)
+    }
+
+    /// Adds the words of a kengdic file, where every line is a gloss of a
+    /// word. Glosses of the same word and hanja end up in one entry.
+    pub fn import_kengdic<R: Read>(&mut self, mut reader: R) -> Result<usize, ImportError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        // (surface, hanja, gloss) columns, unless a header says otherwise
+        let mut columns = (1, 2, 3);
+        let mut n_entries = 0;
+        for (i, line) in text.lines().enumerate() {
+            let fields = line.split('\t').map(|f| f.trim()).collect::<Vec<_>>();
+            if i == 0 && fields.contains(&"surface") {
+                let column = |name, default| {
+                    fields.iter().position(|f| *f == name).unwrap_or(default)
+                };
+                columns = (column("surface", 1), column("hanja", 2), column("gloss", 3));
+                continue;
+            }
+            let field = |index: usize| fields.get(index).map_or("", |f| *f);
+            let (surface, hanja, gloss) = (field(columns.0), field(columns.1), field(columns.2));
+            if surface.is_empty() || gloss.is_empty() {
+                continue;
+            }
+            self.insert(surface,
+                        Entry {
+                            hanja: if has_hanja(hanja) { Some(hanja.to_string()) } else { None },
+                            pos: None,
+                            senses: vec![Sense {
+                                             definition: gloss.to_string(),
+                                             translation: None,
+                                         }],
+                        });
+            n_entries += 1;
+        }
+        Ok(n_entries)
+    }
+}
+
+fn new_entry() -> Entry {
+    Entry {
+        hanja: None,
+        pos: None,
+        senses: Vec::new(),
+    }
+}
+
+/// Returns whether the text has CJK ideographs, as opposed to the origin of
+/// a loanword, or a placeholder like 'NULL'.
+fn has_hanja(text: &str) -> bool {
+    text.chars().any(|c| match c as u32 {
+        0x3400...0x4DBF | 0x4E00...0x9FFF | 0xF900...0xFAFF => true,
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use self::super::{Dictionary, Entry, Sense};
+
+    fn sense(definition: &str, translation: Option<&str>) -> Sense {
+        Sense {
+            definition: definition.to_string(),
+            translation: translation.map(|t| t.to_string()),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let dictionary = Dictionary::parse("# term\tdefinition\n\
+                                            학생\tstudent\n\
+                                            학생\tpupil\n\
+                                            \n\
+                                            선생님 teacher\n\
+                                            사과\t apple \n");
+        assert_eq!(vec![sense("student", None), sense("pupil", None)],
+                   dictionary.lookup("학생")[0].senses);
+        assert_eq!(vec![sense("apple", None)], dictionary.lookup("사과")[0].senses);
+        assert!(dictionary.lookup("선생님").is_empty());
+        assert!(dictionary.lookup("# term").is_empty());
+    }
+
+    #[test]
+    fn krdict() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<LexicalResource>
+  <Lexicon>
+    <LexicalEntry att="id" val="1">
+      <feat att="homonym_number" val="0"/>
+      <Lemma><feat att="writtenForm" val="학생"/></Lemma>
+      <feat att="partOfSpeech" val="명사"/>
+      <feat att="origin" val="學生"/>
+      <Sense val="1">
+        <feat att="definition" val="학교에 다니면서 공부하는 사람."/>
+        <Equivalent>
+          <feat att="language" val="일본어"/>
+          <feat att="lemma" val="学生"/>
+        </Equivalent>
+        <Equivalent>
+          <feat att="language" val="영어"/>
+          <feat att="lemma" val="student"/>
+        </Equivalent>
+      </Sense>
+    </LexicalEntry>
+    <LexicalEntry att="id" val="2">
+      <Lemma><feat att="writtenForm" val="컴퓨터"/></Lemma>
+      <feat att="partOfSpeech" val="명사"/>
+      <feat att="origin" val="computer"/>
+      <Sense val="1">
+        <feat att="definition" val="전자 회로를 이용한 기계."/>
+      </Sense>
+    </LexicalEntry>
+  </Lexicon>
+</LexicalResource>"#;
+        let mut dictionary = Dictionary::default();
+        assert_eq!(2, dictionary.import_krdict(xml.as_bytes()).unwrap());
+        assert_eq!(vec![Entry {
+                            hanja: Some("學生".to_string()),
+                            pos: Some("명사".to_string()),
+                            senses: vec![sense("학교에 다니면서 공부하는 사람.", Some("student"))],
+                        }],
+                   dictionary.lookup("학생"));
+        assert_eq!(None, dictionary.lookup("컴퓨터")[0].hanja);
+        // Importing again changes nothing
+        dictionary.import_krdict(xml.as_bytes()).unwrap();
+        assert_eq!(1, dictionary.lookup("학생").len());
+        assert_eq!(1, dictionary.lookup("학생")[0].senses.len());
+    }
+
+    #[test]
+    fn kengdic() {
+        let tsv = "id\tsurface\thanja\tgloss\tlevel\n\
+                   1\t학생\t學生\tstudent\tA\n\
+                   2\t학생\t學生\tpupil\tA\n\
+                   3\t배\t\tpear\t\n\
+                   4\t배\t\tboat\t\n";
+        let mut dictionary = Dictionary::default();
+        assert_eq!(4, dictionary.import_kengdic(tsv.as_bytes()).unwrap());
+        assert_eq!(vec![sense("student", None), sense("pupil", None)],
+                   dictionary.lookup("학생")[0].senses);
+        assert_eq!(Some("學生".to_string()), dictionary.lookup("학생")[0].hanja);
+        assert_eq!(None, dictionary.lookup("배")[0].hanja);
+        assert_eq!(2, dictionary.lookup("배")[0].senses.len());
+    }
+
+    #[test]
+    fn index() {
+        let mut dictionary = Dictionary::default();
+        dictionary.insert("학생",
+                          Entry {
+                              hanja: None,
+                              pos: None,
+                              senses: vec![sense("student", None)],
+                          });
+        let json = serde_json::to_string(&dictionary).unwrap();
+        assert!(json.contains("\"version\":1"));
+        let loaded = Dictionary::from_text(&json).unwrap();
+        assert_eq!(dictionary.lookup("학생"), loaded.lookup("학생"));
+        // Indexes from before the version field
+        let loaded = Dictionary::from_text(r#"{"entries":{"배":[]}}"#).unwrap();
+        assert_eq!(1, loaded.len());
+        assert!(Dictionary::from_text(r#"{"version":2,"entries":{}}"#).is_err());
+    }
+}
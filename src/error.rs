@@ -0,0 +1,43 @@
+use std::error;
+use std::fmt;
+use std::io;
+use migrate::MigrateError;
+
+/// An error loading or saving a collection, history or journal.
+#[derive(Debug)]
+pub enum Error {
+    Open(io::Error),
+    Read(io::Error),
+    /// The file is not valid JSON, or was written by a newer version.
+    Parse(MigrateError),
+    Write(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Open(ref err) => write!(f, "could not open the file: {}", err),
+            Error::Read(ref err) => write!(f, "could not read the file: {}", err),
+            Error::Parse(ref err) => write!(f, "could not parse the file: {}", err),
+            Error::Write(ref err) => write!(f, "could not write the file: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Open(_) => "could not open the file",
+            Error::Read(_) => "could not read the file",
+            Error::Parse(_) => "could not parse the file",
+            Error::Write(_) => "could not write the file",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Open(ref err) | Error::Read(ref err) | Error::Write(ref err) => Some(err),
+            Error::Parse(ref err) => Some(err),
+        }
+    }
+}
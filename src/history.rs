@@ -1,26 +1,376 @@
-use std::collections::{HashMap, HashSet};
-use serde_json;
+use std::collections::HashMap;
+use source::{Note, Meaning};
+use migrate::{self, MigrateError, HISTORY_VERSION};
+use serde_json::{self, Value};
+use error::Error;
+use date;
+use save;
+
+/// A conflicting note put aside to be resolved later.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PendingNote {
+    pub term: String,
+    pub text: String,
+    pub symbol: Option<String>,
+    pub example: Option<String>,
+    /// The title of the source the note came from.
+    pub source: String,
+    pub lineno: usize,
+}
+
+impl PendingNote {
+    pub fn new(note: &Note, source_title: &str) -> PendingNote {
+        PendingNote {
+            term: note.term.clone(),
+            text: note.meaning.text.clone(),
+            symbol: note.meaning.symbol.clone(),
+            example: note.meaning.example.clone(),
+            source: source_title.to_string(),
+            lineno: note.lineno,
+        }
+    }
+
+    pub fn to_note(&self) -> Note {
+        Note {
+            term: self.term.clone(),
+            meaning: Meaning {
+                text: self.text.clone(),
+                symbol: self.symbol.clone(),
+                example: self.example.clone(),
+            },
+            lineno: self.lineno,
+        }
+    }
+}
+
+/// How a note was handled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Added as the first meaning of its term.
+    Added,
+    /// Already in the collection.
+    Known,
+    Rejected,
+    /// Added next to the other meanings of its term.
+    AddedAlongside,
+    /// Replaced a meaning of its term.
+    Replaced,
+    /// Replaced a meaning of its term with a text of its own.
+    Updated,
+    /// Updated the meaning added from an earlier version of its line.
+    Edited,
+}
+
+impl Outcome {
+    pub fn from_str(text: &str) -> Option<Outcome> {
+        use self::Outcome::*;
+        match text {
+            "added" => Some(Added),
+            "known" => Some(Known),
+            "rejected" => Some(Rejected),
+            "added-alongside" => Some(AddedAlongside),
+            "replaced" => Some(Replaced),
+            "updated" => Some(Updated),
+            "edited" => Some(Edited),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        use self::Outcome::*;
+        match self {
+            Added => "added",
+            Known => "known",
+            Rejected => "rejected",
+            AddedAlongside => "added-alongside",
+            Replaced => "replaced",
+            Updated => "updated",
+            Edited => "edited",
+        }
+    }
+}
+
+/// A handled note. Notes handled before records were kept only have their
+/// meaning.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Record {
+    pub meaning: String,
+    pub outcome: Option<Outcome>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+    /// The title of the source the note came from.
+    pub source: Option<String>,
+    pub lineno: Option<usize>,
+    /// The text the meaning was updated to, for 'Updated' notes.
+    pub replacement: Option<String>,
+    #[serde(default)]
+    pub symbol: Option<String>,
+    #[serde(default)]
+    pub example: Option<String>,
+}
+
+impl Record {
+    /// Records a note handled now.
+    pub fn new(note: &Note, outcome: Outcome, source_title: &str) -> Record {
+        Record {
+            meaning: note.meaning.text.clone(),
+            outcome: Some(outcome),
+            timestamp: Some(date::now()),
+            source: Some(source_title.to_string()),
+            lineno: Some(note.lineno),
+            replacement: None,
+            symbol: note.meaning.symbol.clone(),
+            example: note.meaning.example.clone(),
+        }
+    }
+
+    /// Returns the note the record was made for, as far as it is known.
+    pub fn to_note(&self, term: &str) -> Note {
+        Note {
+            term: term.to_string(),
+            meaning: Meaning {
+                text: self.meaning.clone(),
+                symbol: self.symbol.clone(),
+                example: self.example.clone(),
+            },
+            lineno: self.lineno.unwrap_or(0),
+        }
+    }
+}
+
+/// Which records to return from a history.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub term: Option<String>,
+    pub source: Option<String>,
+    pub outcome: Option<Outcome>,
+    /// The first day to include, as days since the epoch. Undated records
+    /// are left out when a day is given.
+    pub since: Option<u32>,
+    /// The last day to include.
+    pub until: Option<u32>,
+}
+
+impl Filter {
+    fn matches(&self, term: &str, record: &Record) -> bool {
+        let day = record.timestamp.map(date::day_of);
+        self.term.as_ref().map_or(true, |t| t == term) &&
+        self.source.as_ref().map_or(true, |s| record.source.as_ref() == Some(s)) &&
+        self.outcome.map_or(true, |o| record.outcome == Some(o)) &&
+        self.since.map_or(true, |since| day.map_or(false, |d| d >= since)) &&
+        self.until.map_or(true, |until| day.map_or(false, |d| d <= until))
+    }
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct History {
-    handled: HashMap<String, HashSet<String>>,
+    #[serde(default)]
+    version: u32,
+    handled: HashMap<String, Vec<Record>>,
+    #[serde(default)]
+    pending: Vec<PendingNote>,
 }
 
 impl History {
     #[inline]
     pub fn new() -> History {
-        History { handled: HashMap::new() }
+        History {
+            version: HISTORY_VERSION,
+            handled: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Reads a history, upgrading it if it was saved with an older schema.
+    pub fn from_json(json: &str) -> Result<History, MigrateError> {
+        History::migrate_json(json).map(|(history, _)| history)
+    }
+
+    /// Reads a history of any schema version, and returns it along with the
+    /// version it was saved with.
+    pub fn migrate_json(json: &str) -> Result<(History, u32), MigrateError> {
+        let value: Value = serde_json::from_str(json)?;
+        let (value, version) = migrate::migrate_history(value)?;
+        let mut history: History = serde_json::from_value(value)?;
+        history.version = HISTORY_VERSION;
+        Ok((history, version))
+    }
+
+    /// Loads a history, or returns an empty one if the file doesn't exist.
+    pub fn load(path: &str) -> Result<History, Error> {
+        match save::read(path)? {
+            Some(json) => History::from_json(&json).map_err(Error::Parse),
+            None => Ok(History::new()),
+        }
     }
 
-    pub fn from_json(json: &str) -> serde_json::Result<History> {
-        serde_json::from_str(json)
+    /// Saves the history. If `backup` is set, the previous version is kept.
+    pub fn save(&self, path: &str, backup: bool) -> Result<(), Error> {
+        save::write_json(self, path, backup)
     }
 
-    pub fn insert(&mut self, term: String, meaning: String) {
-        self.handled.entry(term).or_insert(HashSet::new()).insert(meaning);
+    /// Records how a note was handled, replacing any earlier record of it.
+    pub fn insert(&mut self, term: String, record: Record) {
+        let records = self.handled.entry(term).or_insert(Vec::new());
+        records.retain(|r| r.meaning != record.meaning);
+        records.push(record);
     }
 
     pub fn contains(&mut self, term: &str, meaning: &str) -> bool {
-        self.handled.get(term).map(|m| m.contains(meaning)).unwrap_or(false)
+        self.record(term, meaning).is_some()
+    }
+
+    pub fn record(&self, term: &str, meaning: &str) -> Option<&Record> {
+        self.handled.get(term).and_then(|r| r.iter().find(|r| r.meaning == meaning))
+    }
+
+    /// Returns the records of the notes of a term.
+    pub fn records(&self, term: &str) -> &[Record] {
+        self.handled.get(term).map(|r| &r[..]).unwrap_or(&[])
+    }
+
+    /// Replaces the records of a term.
+    pub fn set_records(&mut self, term: &str, records: Vec<Record>) {
+        if records.is_empty() {
+            self.handled.remove(term);
+        } else {
+            self.handled.insert(term.to_string(), records);
+        }
+    }
+
+    /// Returns the records matching the filter as (term, record), oldest
+    /// first. Undated records come before all others.
+    pub fn query(&self, filter: &Filter) -> Vec<(&String, &Record)> {
+        let mut found = Vec::new();
+        for (term, records) in &self.handled {
+            for record in records {
+                if filter.matches(term, record) {
+                    found.push((term, record));
+                }
+            }
+        }
+        found.sort_by(|a, b| (a.1.timestamp, a.0).cmp(&(b.1.timestamp, b.0)));
+        found
+    }
+
+    /// Forgets that a note was handled, so that it is offered again.
+    pub fn remove(&mut self, term: &str, meaning: &str) {
+        let now_empty = match self.handled.get_mut(term) {
+            Some(records) => {
+                records.retain(|r| r.meaning != meaning);
+                records.is_empty()
+            }
+            None => false,
+        };
+        if now_empty {
+            self.handled.remove(term);
+        }
+    }
+
+    /// Marks the notes handled for a term as handled for another term as
+    /// well, and moves its pending notes to the other term. The notes of the
+    /// old term are kept, since the sources still contain them.
+    pub fn copy_term(&mut self, term: &str, new_term: &str) {
+        let handled = self.records(term).to_vec();
+        for record in handled {
+            if self.record(new_term, &record.meaning).is_none() {
+                self.insert(new_term.to_string(), record);
+            }
+        }
+        for note in &mut self.pending {
+            if note.term == term {
+                note.term = new_term.to_string();
+            }
+        }
+    }
+
+    /// Queues a conflicting note for later, unless it is already queued.
+    pub fn defer(&mut self, note: PendingNote) {
+        if !self.pending.iter().any(|p| p.term == note.term && p.text == note.text) {
+            self.pending.push(note);
+        }
+    }
+
+    /// Drops the pending notes from the given source.
+    pub fn remove_pending_from(&mut self, source_title: &str) {
+        self.pending.retain(|p| p.source != source_title);
+    }
+
+    /// Forgets every note handled from the given source, whatever its
+    /// outcome, so that importing the source again offers them anew.
+    pub fn remove_source(&mut self, source_title: &str) {
+        for records in self.handled.values_mut() {
+            records.retain(|r| r.source.as_ref().map_or(true, |s| s != source_title));
+        }
+        self.handled.retain(|_, records| !records.is_empty());
+        self.remove_pending_from(source_title);
+    }
+
+    /// Returns the notes waiting to be resolved.
+    #[inline]
+    pub fn pending(&self) -> &[PendingNote] {
+        &self.pending
+    }
+
+    /// Removes a note from the notes waiting to be resolved.
+    pub fn remove_pending(&mut self, term: &str, meaning: &str) {
+        self.pending.retain(|p| !(p.term == term && p.text == meaning));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{History, PendingNote, Outcome, Record};
+    use serde_json;
+    use source::Note;
+    use testing;
+
+    /// A note with a symbol and an example.
+    fn note(term: &str, text: &str, lineno: usize) -> Note {
+        let mut note = testing::note(term, text);
+        note.meaning.symbol = Some("N".to_string());
+        note.meaning.example = Some("학생이에요.".to_string());
+        note.lineno = lineno;
+        note
+    }
+
+    #[test]
+    fn pending() {
+        let mut his = History::new();
+        his.defer(PendingNote::new(&note("학생", "pupil", 3), "One"));
+        his.defer(PendingNote::new(&note("학생", "pupil", 5), "Two"));
+        his.defer(PendingNote::new(&note("사과", "apology", 4), "One"));
+        assert_eq!(2, his.pending().len());
+
+        let json = serde_json::to_string(&his).unwrap();
+        let mut his = History::from_json(&json).unwrap();
+        assert_eq!(2, his.pending().len());
+        assert_eq!("One", his.pending()[0].source);
+        let restored = his.pending()[0].to_note();
+        assert_eq!(("학생", "pupil", 3),
+                   (&restored.term[..], &restored.meaning.text[..], restored.lineno));
+        assert_eq!(Some("N".to_string()), restored.meaning.symbol);
+        assert_eq!(Some("학생이에요.".to_string()), restored.meaning.example);
+
+        his.remove_pending("학생", "pupil");
+        assert_eq!(1, his.pending().len());
+        assert_eq!(PendingNote::new(&note("사과", "apology", 4), "One"), his.pending()[0]);
+        his.remove_pending_from("One");
+        assert!(his.pending().is_empty());
+    }
+
+    #[test]
+    fn record_note() {
+        let record = Record::new(&note("학생", "pupil", 3), Outcome::Rejected, "One");
+        let restored = record.to_note("학생");
+        assert_eq!(Some("N".to_string()), restored.meaning.symbol);
+        assert_eq!(Some("학생이에요.".to_string()), restored.meaning.example);
+
+        // Records saved before symbols and examples were kept
+        let his = History::from_json(r#"{"version":1,"handled":{"학생":[{"meaning":"pupil",
+            "outcome":"Rejected","timestamp":null,"source":null,"lineno":null,
+            "replacement":null}]}}"#)
+            .unwrap();
+        assert_eq!(None, his.record("학생", "pupil").unwrap().symbol);
     }
 }
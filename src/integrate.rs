@@ -0,0 +1,685 @@
+use collection::{Collection, SourceAction};
+use history::{History, Filter, PendingNote, Record, Outcome};
+use prompt::{Prompter, PromptError};
+use session::{Session, SessionError};
+use source::{Note, Source};
+
+/// The source title of revisited notes whose source was not recorded.
+pub const UNKNOWN_SOURCE: &'static str = "unknown";
+
+/// How to resolve notes whose term already has other meanings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    Prompt,
+    Add,
+    Ignore,
+    ReplaceNewest,
+    Defer,
+}
+
+impl ConflictPolicy {
+    pub fn from_str(text: &str) -> Option<ConflictPolicy> {
+        use self::ConflictPolicy::*;
+        match text {
+            "prompt" => Some(Prompt),
+            "add" => Some(Add),
+            "ignore" => Some(Ignore),
+            "replace-newest" => Some(ReplaceNewest),
+            "defer" => Some(Defer),
+            _ => None,
+        }
+    }
+
+    /// Decides a conflict without asking, or returns None for 'prompt'.
+    /// 'add' and 'replace-newest' decide `Edit` for an edited line, so its
+    /// meaning is updated in place instead of added or replacing the newest
+    /// meaning; 'ignore' and 'defer' treat edited lines like any other.
+    pub fn decide(self, n_meanings: usize, note: &Note, edited: Option<usize>) -> Option<Decision> {
+        use self::ConflictPolicy::*;
+        match (self, edited) {
+            (Add, Some(index)) | (ReplaceNewest, Some(index)) => return Some(Decision::Edit(index)),
+            _ => {}
+        }
+        match self {
+            Prompt => None,
+            Add => Some(Decision::Add),
+            Ignore => Some(Decision::Reject),
+            ReplaceNewest => Some(Decision::Update(n_meanings - 1, note.meaning.text.clone())),
+            Defer => Some(Decision::Defer),
+        }
+    }
+}
+
+/// What to do with a conflicting note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Reject,
+    /// Add the note next to the other meanings of its term.
+    Add,
+    /// Replace a meaning with the given text.
+    Update(usize, String),
+    /// Update the meaning that came from an earlier version of the note's
+    /// line to the note.
+    Edit(usize),
+    /// Leave the note for a later run.
+    Defer,
+}
+
+/// What integrating a note did, or would do on a dry run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Handled in an earlier run.
+    Skipped,
+    /// Already in the collection.
+    Known,
+    Added,
+    /// Left unresolved by a dry run.
+    Conflict,
+    AddedAlongside,
+    Ignored,
+    Replaced,
+    /// Updated the meaning of an edited line.
+    Edited,
+    Deferred,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        use self::Action::*;
+        match self {
+            Skipped => "skipped",
+            Known => "known",
+            Added => "added",
+            Conflict => "conflict",
+            AddedAlongside => "added-alongside",
+            Ignored => "ignored",
+            Replaced => "replaced",
+            Edited => "edited",
+            Deferred => "deferred",
+        }
+    }
+}
+
+/// What happened to the notes of a source.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub added: usize,
+    pub skipped: usize,
+    pub unresolved: usize,
+    pub added_alongside: usize,
+    pub ignored: usize,
+    pub replaced: usize,
+    pub edited: usize,
+    pub deferred: usize,
+}
+
+impl Summary {
+    pub fn count(&mut self, action: Action) {
+        use self::Action::*;
+        match action {
+            Skipped | Known => self.skipped += 1,
+            Added => self.added += 1,
+            Conflict => self.unresolved += 1,
+            AddedAlongside => self.added_alongside += 1,
+            Ignored => self.ignored += 1,
+            Replaced => self.replaced += 1,
+            Edited => self.edited += 1,
+            Deferred => self.deferred += 1,
+        }
+    }
+}
+
+/// The result of integrating a note without asking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Done {
+        action: Action,
+        /// Whether the collection or history changed.
+        changed: bool,
+    },
+    /// The term of the note has other meanings, so a decision is needed.
+    Conflict {
+        /// The meaning that came from an earlier version of the note's
+        /// line, if any.
+        edited: Option<usize>,
+    },
+}
+
+/// Integrates a note that needs no decision: one that was handled before,
+/// one that is already in the collection or the first meaning of its term.
+pub fn integrate_note(collection: &mut Collection,
+                      history: &mut History,
+                      note: &Note,
+                      source_title: &str)
+                      -> Step {
+    let (term, text) = (&note.term, &note.meaning.text);
+    if history.contains(term, text) {
+        let confirmed = collection.confirm_meaning(term, text, source_title, Some(note.lineno));
+        Step::Done {
+            action: Action::Skipped,
+            changed: confirmed,
+        }
+    } else if collection.contains(term, text) {
+        history.insert(term.clone(), Record::new(note, Outcome::Known, source_title));
+        collection.confirm_meaning(term, text, source_title, Some(note.lineno));
+        Step::Done {
+            action: Action::Known,
+            changed: true,
+        }
+    } else if collection.meanings(term).is_empty() {
+        history.insert(term.clone(), Record::new(note, Outcome::Added, source_title));
+        collection.add_meaning(term.clone(), note.meaning.clone(), source_title, Some(note.lineno));
+        Step::Done {
+            action: Action::Added,
+            changed: true,
+        }
+    } else {
+        Step::Conflict { edited: collection.meaning_from_line(term, source_title, note.lineno) }
+    }
+}
+
+/// Applies the decision made for a conflicting note.
+pub fn apply_decision(collection: &mut Collection,
+                      history: &mut History,
+                      note: &Note,
+                      source_title: &str,
+                      decision: Decision)
+                      -> Action {
+    match decision {
+        Decision::Reject => {
+            history.insert(note.term.clone(),
+                           Record::new(note, Outcome::Rejected, source_title));
+            Action::Ignored
+        }
+        Decision::Add => {
+            history.insert(note.term.clone(),
+                           Record::new(note, Outcome::AddedAlongside, source_title));
+            collection.add_meaning(note.term.clone(),
+                                   note.meaning.clone(),
+                                   source_title,
+                                   Some(note.lineno));
+            Action::AddedAlongside
+        }
+        Decision::Update(index, meaning) => {
+            let mut record = Record::new(note, Outcome::Replaced, source_title);
+            let action = if meaning == note.meaning.text {
+                SourceAction::Replace
+            } else {
+                record.outcome = Some(Outcome::Updated);
+                record.replacement = Some(meaning.clone());
+                SourceAction::Update
+            };
+            history.insert(note.term.clone(), record);
+            collection.replace_meaning(&note.term,
+                                       index,
+                                       meaning,
+                                       None,
+                                       source_title,
+                                       Some(note.lineno),
+                                       action)
+                .expect("INVARIANT!");
+            Action::Replaced
+        }
+        Decision::Edit(index) => {
+            history.insert(note.term.clone(),
+                           Record::new(note, Outcome::Edited, source_title));
+            collection.replace_meaning(&note.term,
+                                       index,
+                                       note.meaning.text.clone(),
+                                       note.meaning.symbol.clone(),
+                                       source_title,
+                                       Some(note.lineno),
+                                       SourceAction::Update)
+                .expect("INVARIANT!");
+            Action::Edited
+        }
+        Decision::Defer => {
+            history.defer(PendingNote::new(note, source_title));
+            Action::Deferred
+        }
+    }
+}
+
+/// Decides a conflicting note with the policy, or else by asking the
+/// prompter, and applies the decision.
+pub fn resolve_conflict<P: Prompter + ?Sized>(collection: &mut Collection,
+                                              history: &mut History,
+                                              note: &Note,
+                                              source_title: &str,
+                                              edited: Option<usize>,
+                                              policy: ConflictPolicy,
+                                              prompter: &mut P)
+                                              -> Result<Action, PromptError> {
+    let n_meanings = collection.meanings(&note.term).len();
+    let decision = match policy.decide(n_meanings, note, edited) {
+        Some(decision) => decision,
+        None => prompter.decide(collection, note, source_title, edited)?,
+    };
+    Ok(apply_decision(collection, history, note, source_title, decision))
+}
+
+/// What happened to a note, as reported while a session goes through notes.
+#[derive(Debug)]
+pub struct Handled<'a> {
+    pub note: &'a Note,
+    pub action: Action,
+    /// The meaning that came from an earlier version of the note's line.
+    pub edits: Option<String>,
+}
+
+/// Integrates every note of a source in a session, reporting what happened
+/// to each note along with the collection after it. Without a prompter,
+/// conflicts that the policy leaves open are reported as `Action::Conflict`
+/// and left alone. If the prompter fails, the notes before it stay
+/// integrated and are saved.
+pub fn integrate_source<P, F>(session: &mut Session,
+                              source: &Source,
+                              policy: ConflictPolicy,
+                              mut prompter: Option<&mut P>,
+                              mut report: F)
+                              -> Result<Summary, SessionError>
+    where P: Prompter + ?Sized,
+          F: FnMut(&Handled, &Collection)
+{
+    let mut summary = Summary::default();
+    for note in &source.contents {
+        session.touch(&note.term);
+        let step = integrate_note(&mut session.collection,
+                                  &mut session.history,
+                                  note,
+                                  &source.title);
+        let mut edits = None;
+        let action = match step {
+            Step::Done { action, changed } => {
+                if changed {
+                    session.changed();
+                }
+                action
+            }
+            Step::Conflict { edited } => {
+                edits = edited.map(|i| session.collection.meanings(&note.term)[i].text.clone());
+                let res = match prompter {
+                    Some(ref mut prompter) => {
+                        session.resolve_conflict(note,
+                                                 &source.title,
+                                                 edited,
+                                                 policy,
+                                                 &mut **prompter)
+                    }
+                    None => {
+                        let n_meanings = session.collection.meanings(&note.term).len();
+                        match policy.decide(n_meanings, note, edited) {
+                            Some(decision) => {
+                                session.changed();
+                                Ok(apply_decision(&mut session.collection,
+                                                  &mut session.history,
+                                                  note,
+                                                  &source.title,
+                                                  decision))
+                            }
+                            None => Ok(Action::Conflict),
+                        }
+                    }
+                };
+                match res {
+                    Ok(action) => action,
+                    Err(err) => {
+                        // Save and quit
+                        session.save()?;
+                        return Err(err);
+                    }
+                }
+            }
+        };
+        summary.count(action);
+        let handled = Handled {
+            note: note,
+            action: action,
+            edits: edits,
+        };
+        report(&handled, &session.collection);
+        session.check_interrupted()?;
+        session.checkpoint()?;
+    }
+    Ok(summary)
+}
+
+/// Goes through the notes deferred by earlier runs in a session, asking the
+/// prompter about each. A note stays pending until its decision has been
+/// applied, so it is kept if the prompter fails or Ctrl-C quits the prompt.
+pub fn resolve_pending<P, F>(session: &mut Session,
+                             prompter: &mut P,
+                             mut report: F)
+                             -> Result<Summary, SessionError>
+    where P: Prompter + ?Sized,
+          F: FnMut(&Handled, &Collection)
+{
+    let mut summary = Summary::default();
+    for entry in session.history.pending().to_vec() {
+        let note = entry.to_note();
+        session.touch(&note.term);
+        let step = integrate_note(&mut session.collection,
+                                  &mut session.history,
+                                  &note,
+                                  &entry.source);
+        let action = match step {
+            Step::Done { action, .. } => action,
+            Step::Conflict { edited } => {
+                let res = session.resolve_conflict(&note,
+                                                   &entry.source,
+                                                   edited,
+                                                   ConflictPolicy::Prompt,
+                                                   prompter);
+                match res {
+                    Ok(action) => action,
+                    Err(err) => {
+                        session.save()?;
+                        return Err(err);
+                    }
+                }
+            }
+        };
+        // Deferring it again leaves it in the queue
+        if action != Action::Deferred {
+            session.history.remove_pending(&entry.term, &entry.text);
+            session.changed();
+        }
+        summary.count(action);
+        let handled = Handled {
+            note: &note,
+            action: action,
+            edits: None,
+        };
+        report(&handled, &session.collection);
+        session.check_interrupted()?;
+        session.checkpoint()?;
+    }
+    Ok(summary)
+}
+
+/// Offers the rejected notes matching the filter to the prompter again, so
+/// that they can still be added. Notes handled before outcomes were recorded
+/// count as rejected if they aren't in the collection. `announce` is called
+/// with the record of a note before the prompter is asked about it.
+pub fn revisit_rejected<P, A, F>(session: &mut Session,
+                                 filter: &Filter,
+                                 prompter: &mut P,
+                                 mut announce: A,
+                                 mut report: F)
+                                 -> Result<Summary, SessionError>
+    where P: Prompter + ?Sized,
+          A: FnMut(&Record),
+          F: FnMut(&Handled, &Collection)
+{
+    let rejected = session.history
+        .query(filter)
+        .into_iter()
+        .filter(|&(term, record)| match record.outcome {
+            Some(outcome) => outcome == Outcome::Rejected,
+            None => !session.collection.contains(term, &record.meaning),
+        })
+        .map(|(term, record)| (term.clone(), record.clone()))
+        .collect::<Vec<_>>();
+    let mut summary = Summary::default();
+    for (term, record) in rejected {
+        let source = record.source.clone().unwrap_or_else(|| UNKNOWN_SOURCE.to_string());
+        let note = record.to_note(&term);
+        session.touch(&note.term);
+        let action = if session.collection.contains(&note.term, &note.meaning.text) {
+            Action::Known
+        } else if session.collection.meanings(&note.term).is_empty() {
+            // Removed since, so there is nothing to conflict with
+            session.history.insert(note.term.clone(), Record::new(&note, Outcome::Added, &source));
+            session.collection
+                .add_meaning(note.term.clone(), note.meaning.clone(), &source, record.lineno);
+            session.changed();
+            Action::Added
+        } else {
+            announce(&record);
+            let res = session.resolve_conflict(&note,
+                                               &source,
+                                               None,
+                                               ConflictPolicy::Prompt,
+                                               prompter);
+            match res {
+                Ok(action) => action,
+                Err(err) => {
+                    session.save()?;
+                    return Err(err);
+                }
+            }
+        };
+        summary.count(action);
+        let handled = Handled {
+            note: &note,
+            action: action,
+            edits: None,
+        };
+        report(&handled, &session.collection);
+        session.check_interrupted()?;
+        session.checkpoint()?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{integrate_source, resolve_pending, revisit_rejected, ConflictPolicy,
+                      Decision, Summary};
+    use self::super::ConflictPolicy::*;
+    use collection::Collection;
+    use history::{History, Filter, Outcome};
+    use prompt::ScriptedPrompter;
+    use session::{Session, SessionError};
+    use source::Source;
+    use testing::{note, source};
+
+    fn session() -> Session {
+        Session::new(Collection::new(), History::new())
+    }
+
+    fn run(session: &mut Session,
+           source: &Source,
+           policy: ConflictPolicy,
+           prompter: &mut ScriptedPrompter)
+           -> Result<Summary, SessionError> {
+        integrate_source(session, source, policy, Some(prompter), |_, _| {})
+    }
+
+    #[test]
+    fn scripted() {
+        let mut session = session();
+        let mut prompter = ScriptedPrompter::new();
+        let one = source("One", &[("학생", "student"), ("사과", "apple")]);
+        run(&mut session, &one, Prompt, &mut prompter).unwrap();
+
+        prompter.insert("학생", "pupil", Decision::Reject);
+        prompter.insert("사과", "apology", Decision::Add);
+        let two = source("Two", &[("학생", "pupil"), ("사과", "apology"), ("학생", "student")]);
+        let summary = run(&mut session, &two, Prompt, &mut prompter).unwrap();
+        assert_eq!((1, 1, 1), (summary.ignored, summary.added_alongside, summary.skipped));
+        assert_eq!(1, session.collection.meanings("학생").len());
+        assert_eq!(2, session.collection.meanings("사과").len());
+        assert_eq!(Some(Outcome::Rejected),
+                   session.history.record("학생", "pupil").unwrap().outcome);
+
+        // The policy decides before the prompter is asked
+        let three = source("Three", &[("학생", "elev")]);
+        run(&mut session, &three, Add, &mut prompter).unwrap();
+        assert_eq!(2, session.collection.meanings("학생").len());
+        let four = source("Four", &[("학생", "disciple")]);
+        assert!(run(&mut session, &four, Prompt, &mut prompter).is_err());
+    }
+
+    #[test]
+    fn decide() {
+        let note = note("학생", "pupil");
+        assert_eq!(None, Prompt.decide(2, &note, None));
+        assert_eq!(None, Prompt.decide(2, &note, Some(0)));
+        assert_eq!(Some(Decision::Add), Add.decide(2, &note, None));
+        assert_eq!(Some(Decision::Edit(0)), Add.decide(2, &note, Some(0)));
+        assert_eq!(Some(Decision::Reject), Ignore.decide(2, &note, None));
+        assert_eq!(Some(Decision::Reject), Ignore.decide(2, &note, Some(0)));
+        assert_eq!(Some(Decision::Update(1, "pupil".to_string())),
+                   ReplaceNewest.decide(2, &note, None));
+        assert_eq!(Some(Decision::Edit(0)), ReplaceNewest.decide(2, &note, Some(0)));
+        assert_eq!(Some(Decision::Defer), Defer.decide(2, &note, None));
+        assert_eq!(Some(Decision::Defer), Defer.decide(2, &note, Some(0)));
+    }
+
+    /// Integrates a conflicting note with the given policy into a collection
+    /// that knows 학생 as "student" and "learner".
+    fn conflict(policy: ConflictPolicy) -> Session {
+        let mut session = session();
+        let mut prompter = ScriptedPrompter::new();
+        let one = source("One", &[("학생", "student"), ("학생", "learner")]);
+        run(&mut session, &one, Add, &mut prompter).unwrap();
+        let two = source("Two", &[("학생", "pupil")]);
+        run(&mut session, &two, policy, &mut prompter).unwrap();
+        session
+    }
+
+    fn texts(col: &Collection, term: &str) -> Vec<String> {
+        col.meanings(term).iter().map(|m| m.text.clone()).collect()
+    }
+
+    #[test]
+    fn add() {
+        let session = conflict(Add);
+        assert_eq!(vec!["student", "learner", "pupil"], texts(&session.collection, "학생"));
+    }
+
+    #[test]
+    fn ignore() {
+        let session = conflict(Ignore);
+        assert_eq!(vec!["student", "learner"], texts(&session.collection, "학생"));
+        assert_eq!(Some(Outcome::Rejected),
+                   session.history.record("학생", "pupil").unwrap().outcome);
+    }
+
+    #[test]
+    fn replace_newest() {
+        let session = conflict(ReplaceNewest);
+        assert_eq!(vec!["student", "pupil"], texts(&session.collection, "학생"));
+    }
+
+    #[test]
+    fn defer() {
+        let session = conflict(Defer);
+        assert_eq!(vec!["student", "learner"], texts(&session.collection, "학생"));
+        assert_eq!(1, session.history.pending().len());
+        assert!(session.history.record("학생", "pupil").is_none());
+    }
+
+    #[test]
+    fn prompt() {
+        let mut session = session();
+        let mut prompter = ScriptedPrompter::new();
+        let one = source("One", &[("학생", "student")]);
+        run(&mut session, &one, Add, &mut prompter).unwrap();
+        prompter.insert("학생", "pupil", Decision::Update(0, "pupil".to_string()));
+        let two = source("Two", &[("학생", "pupil")]);
+        run(&mut session, &two, Prompt, &mut prompter).unwrap();
+        assert_eq!(vec!["pupil"], texts(&session.collection, "학생"));
+    }
+
+    #[test]
+    fn edited() {
+        for &policy in &[Add, ReplaceNewest] {
+            let mut session = conflict(Add);
+            let mut prompter = ScriptedPrompter::new();
+            // "learner" came from line 3 of One, which now says "trainee"
+            let edited = source("One", &[("학생", "student"), ("학생", "trainee")]);
+            let summary = run(&mut session, &edited, policy, &mut prompter).unwrap();
+            assert_eq!(1, summary.edited);
+            assert_eq!(vec!["student", "trainee", "pupil"], texts(&session.collection, "학생"));
+        }
+        for &(policy, outcome) in &[(Ignore, Some(Outcome::Rejected)), (Defer, None)] {
+            let mut session = conflict(Add);
+            let mut prompter = ScriptedPrompter::new();
+            let edited = source("One", &[("학생", "student"), ("학생", "trainee")]);
+            run(&mut session, &edited, policy, &mut prompter).unwrap();
+            assert_eq!(vec!["student", "learner", "pupil"], texts(&session.collection, "학생"));
+            assert_eq!(outcome,
+                       session.history.record("학생", "trainee").and_then(|r| r.outcome));
+        }
+    }
+
+    #[test]
+    fn reimport() {
+        let mut session = session();
+        let mut prompter = ScriptedPrompter::new();
+        let one = source("One", &[("학생", "student"), ("사과", "apple")]);
+        run(&mut session, &one, Add, &mut prompter).unwrap();
+        prompter.insert("학생", "pupil", Decision::Reject);
+        prompter.insert("사과", "apology", Decision::Update(0, "apology (sorry)".to_string()));
+        let two = source("Two", &[("학생", "pupil"), ("사과", "apology")]);
+        run(&mut session, &two, Prompt, &mut prompter).unwrap();
+        assert_eq!(vec!["apology (sorry)"], texts(&session.collection, "사과"));
+
+        let unimport = session.collection.unimport("Two").unwrap();
+        for &(ref term, ref text) in &unimport.undone {
+            session.history.remove(term, text);
+        }
+        session.history.remove_source("Two");
+        assert_eq!(vec!["apple"], texts(&session.collection, "사과"));
+        assert!(session.history.record("학생", "pupil").is_none());
+        assert!(session.history.record("사과", "apology").is_none());
+        assert!(session.history.record("학생", "student").is_some());
+
+        // Both notes conflict again instead of being skipped
+        let summary = run(&mut session, &two, Defer, &mut prompter).unwrap();
+        assert_eq!((2, 0), (summary.deferred, summary.skipped));
+    }
+
+    #[test]
+    fn resolve() {
+        let mut session = conflict(Defer);
+        let two = source("Two", &[("학생", "pupil"), ("학생", "learner"), ("학생", "scholar")]);
+        run(&mut session, &two, Defer, &mut ScriptedPrompter::new()).unwrap();
+        assert_eq!(2, session.history.pending().len());
+
+        // No decision for "scholar": the notes before it are resolved, and
+        // it stays pending
+        let mut prompter = ScriptedPrompter::new();
+        prompter.insert("학생", "pupil", Decision::Add);
+        assert!(resolve_pending(&mut session, &mut prompter, |_, _| {}).is_err());
+        assert_eq!(vec!["scholar"],
+                   session.history.pending().iter().map(|p| &p.text[..]).collect::<Vec<_>>());
+
+        prompter.insert("학생", "scholar", Decision::Defer);
+        let summary = resolve_pending(&mut session, &mut prompter, |_, _| {}).unwrap();
+        assert_eq!(1, summary.deferred);
+        assert_eq!(1, session.history.pending().len());
+
+        prompter.insert("학생", "scholar", Decision::Reject);
+        resolve_pending(&mut session, &mut prompter, |_, _| {}).unwrap();
+        assert!(session.history.pending().is_empty());
+        assert_eq!(vec!["student", "learner", "pupil"], texts(&session.collection, "학생"));
+    }
+
+    #[test]
+    fn revisit() {
+        let mut session = conflict(Ignore);
+        let mut prompter = ScriptedPrompter::new();
+        let three = source("Three", &[("사과", "apple")]);
+        run(&mut session, &three, Add, &mut prompter).unwrap();
+        let four = source("Four", &[("사과", "apology")]);
+        run(&mut session, &four, Ignore, &mut prompter).unwrap();
+        session.collection.remove_meaning("사과", 0).unwrap();
+
+        // The rejected note of a removed term is added without asking
+        prompter.insert("학생", "pupil", Decision::Add);
+        let mut announced = Vec::new();
+        let summary = revisit_rejected(&mut session,
+                                       &Filter::default(),
+                                       &mut prompter,
+                                       |record| announced.push(record.meaning.clone()),
+                                       |_, _| {})
+            .unwrap();
+        assert_eq!((1, 1), (summary.added, summary.added_alongside));
+        assert_eq!(vec!["pupil"], announced);
+        assert_eq!(vec!["apology"], texts(&session.collection, "사과"));
+        assert_eq!(vec!["student", "learner", "pupil"], texts(&session.collection, "학생"));
+    }
+}
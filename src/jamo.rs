@@ -0,0 +1,163 @@
+/*
+Decomposition of Hangul syllable blocks into their jamo.
+A syllable block is encoded as 0xAC00 + (initial * 21 + medial) * 28 + final,
+where a final of 0 means that the syllable has no final consonant (batchim).
+The jamo are returned as compatibility jamo (U+3131..U+3163), since those are
+what users type and what displays nicely on its own.
+*/
+
+const SYLLABLE_START: u32 = 0xAC00;
+const SYLLABLE_END: u32 = 0xD7A3;
+
+const INITIALS: [char; 19] = ['ㄱ', 'ㄲ', 'ㄴ', 'ㄷ', 'ㄸ', 'ㄹ', 'ㅁ', 'ㅂ', 'ㅃ', 'ㅅ', 'ㅆ', 'ㅇ',
+                              'ㅈ', 'ㅉ', 'ㅊ', 'ㅋ', 'ㅌ', 'ㅍ', 'ㅎ'];
+
+const MEDIALS: [char; 21] = ['ㅏ', 'ㅐ', 'ㅑ', 'ㅒ', 'ㅓ', 'ㅔ', 'ㅕ', 'ㅖ', 'ㅗ', 'ㅘ', 'ㅙ', 'ㅚ',
+                             'ㅛ', 'ㅜ', 'ㅝ', 'ㅞ', 'ㅟ', 'ㅠ', 'ㅡ', 'ㅢ', 'ㅣ'];
+
+const FINALS: [char; 27] = ['ㄱ', 'ㄲ', 'ㄳ', 'ㄴ', 'ㄵ', 'ㄶ', 'ㄷ', 'ㄹ', 'ㄺ', 'ㄻ', 'ㄼ', 'ㄽ',
+                            'ㄾ', 'ㄿ', 'ㅀ', 'ㅁ', 'ㅂ', 'ㅄ', 'ㅅ', 'ㅆ', 'ㅇ', 'ㅈ', 'ㅊ', 'ㅋ',
+                            'ㅌ', 'ㅍ', 'ㅎ'];
+
+/// The jamo making up a Hangul syllable block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Syllable {
+    pub initial: char,
+    pub medial: char,
+    pub final_: Option<char>,
+}
+
+impl Syllable {
+    /// Returns the jamo of the syllable in writing order.
+    pub fn jamo(&self) -> Vec<char> {
+        let mut jamo = vec![self.initial, self.medial];
+        if let Some(final_) = self.final_ {
+            jamo.push(final_);
+        }
+        jamo
+    }
+}
+
+/// Decomposes a Hangul syllable block into its jamo.
+pub fn decompose(ch: char) -> Option<Syllable> {
+    let code = ch as u32;
+    if code < SYLLABLE_START || code > SYLLABLE_END {
+        return None;
+    }
+    let index = (code - SYLLABLE_START) as usize;
+    let final_index = index % 28;
+    Some(Syllable {
+        initial: INITIALS[index / (21 * 28)],
+        medial: MEDIALS[(index % (21 * 28)) / 28],
+        final_: if final_index == 0 {
+            None
+        } else {
+            Some(FINALS[final_index - 1])
+        },
+    })
+}
+
+/// Returns whether the character is a lone consonant (like 'ㄱ').
+#[inline]
+pub fn is_consonant(ch: char) -> bool {
+    INITIALS.contains(&ch) || FINALS.contains(&ch)
+}
+
+/// Returns whether the character is a compatibility jamo (like 'ㄱ' or 'ㅏ').
+#[inline]
+pub fn is_jamo(ch: char) -> bool {
+    ch >= 'ㄱ' && ch <= 'ㆎ'
+}
+
+/// Returns whether the character is a Hangul syllable block or a lone jamo.
+#[inline]
+pub fn is_hangul(ch: char) -> bool {
+    decompose(ch).is_some() || is_jamo(ch)
+}
+
+/// Returns whether a character of an initial consonant (초성) query matches a
+/// character of a term: a lone consonant matches the syllables starting with
+/// it, and anything else must match exactly.
+pub fn matches_initial(query: char, ch: char) -> bool {
+    query == ch ||
+    (is_consonant(query) && decompose(ch).map(|s| s.initial == query).unwrap_or(false))
+}
+
+/// Returns the jamo of a character, or the character itself if it isn't a
+/// Hangul syllable block.
+pub fn char_jamo(ch: char) -> Vec<char> {
+    decompose(ch).map(|s| s.jamo()).unwrap_or_else(|| vec![ch])
+}
+
+/// Decomposes every syllable block in the text into jamo.
+pub fn to_jamo(text: &str) -> Vec<char> {
+    text.chars().flat_map(|ch| char_jamo(ch)).collect()
+}
+
+/// Returns the Levenshtein distance between two sequences.
+pub fn edit_distance<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    let mut row = (0..b.len() + 1).collect::<Vec<_>>();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if x == y {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Returns the number of jamo edits needed to turn one text into another.
+#[inline]
+pub fn jamo_distance(a: &str, b: &str) -> usize {
+    edit_distance(&to_jamo(a), &to_jamo(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{decompose, to_jamo, jamo_distance, matches_initial, Syllable};
+
+    #[test]
+    fn syllables() {
+        assert_eq!(Some(Syllable {
+                       initial: 'ㄱ',
+                       medial: 'ㅏ',
+                       final_: None,
+                   }),
+                   decompose('가'));
+        assert_eq!(Some(Syllable {
+                       initial: 'ㅎ',
+                       medial: 'ㅣ',
+                       final_: Some('ㅎ'),
+                   }),
+                   decompose('힣'));
+        assert_eq!(None, decompose('ㄱ'));
+    }
+
+    #[test]
+    fn initials() {
+        assert!(matches_initial('ㄱ', '감'));
+        assert!(matches_initial('감', '감'));
+        assert!(matches_initial('ㄴ', 'ㄴ'));
+        assert!(!matches_initial('가', '감'));
+        assert!(!matches_initial('ㅅ', '감'));
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(vec!['ㄱ', 'ㅏ', 'ㅁ', '~', 'ㅅ', 'ㅏ'], to_jamo("감~사"));
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(0, jamo_distance("감사합니다", "감사합니다"));
+        assert_eq!(1, jamo_distance("감사합니다", "감사함니다"));
+        assert_eq!(1, jamo_distance("괜찮다", "괜찬다"));
+    }
+}
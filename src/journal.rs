@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use collection::{Collection, ColMeaning};
+use history::{History, PendingNote, Record};
+use serde_json::{self, Value};
+use migrate::{self, MigrateError, JOURNAL_VERSION};
+use error::Error;
+use save;
+
+/// What a run did to a term.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TermChange {
+    pub term: String,
+    /// The meanings of the term before the run, or None if it had none.
+    pub before: Option<Vec<ColMeaning>>,
+    pub after: Option<Vec<ColMeaning>>,
+    /// The history records of the term before the run.
+    pub records_before: Vec<Record>,
+    pub records_after: Vec<Record>,
+    /// The meanings the run marked as handled, for changes journaled before
+    /// the history records were. Such changes have no records.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handled: Option<Vec<String>>,
+}
+
+/// A command that changed the collection and history, like an integrate run.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Run {
+    pub id: u32,
+    pub command: String,
+    pub sources: Vec<String>,
+    /// Seconds since the Unix epoch.
+    pub started: u64,
+    /// Whether the run got to the end, rather than stopping at an error or
+    /// being interrupted.
+    pub complete: bool,
+    pub reverted: bool,
+    pub changes: Vec<TermChange>,
+    /// The conflicts the run put in the pending queue.
+    pub deferred: Vec<PendingNote>,
+    /// The conflicts the run took out of the pending queue.
+    pub resolved: Vec<PendingNote>,
+}
+
+/// The runs made on a collection, saved next to it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Journal {
+    #[serde(default)]
+    version: u32,
+    runs: Vec<Run>,
+    next_id: u32,
+}
+
+impl Journal {
+    #[inline]
+    pub fn new() -> Journal {
+        Journal {
+            version: JOURNAL_VERSION,
+            runs: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Reads a journal, upgrading it if it was saved with an older schema.
+    pub fn from_json(json: &str) -> Result<Journal, MigrateError> {
+        Journal::migrate_json(json).map(|(journal, _)| journal)
+    }
+
+    /// Reads a journal of any schema version, and returns it along with the
+    /// version it was saved with.
+    pub fn migrate_json(json: &str) -> Result<(Journal, u32), MigrateError> {
+        let value: Value = serde_json::from_str(json)?;
+        let (value, version) = migrate::migrate_journal(value)?;
+        let mut journal: Journal = serde_json::from_value(value)?;
+        journal.version = JOURNAL_VERSION;
+        Ok((journal, version))
+    }
+
+    /// Loads a journal, or returns an empty one if the file doesn't exist.
+    pub fn load(path: &str) -> Result<Journal, Error> {
+        match save::read(path)? {
+            Some(json) => Journal::from_json(&json).map_err(Error::Parse),
+            None => Ok(Journal::new()),
+        }
+    }
+
+    /// Saves the journal, without backups.
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        save::write_json(self, path, false)
+    }
+
+    /// Returns where the journal of a collection is kept.
+    pub fn path_for(colpath: &str) -> String {
+        format!("{}.journal", colpath)
+    }
+
+    #[inline]
+    pub fn runs(&self) -> &[Run] {
+        &self.runs
+    }
+
+    pub fn run_mut(&mut self, id: u32) -> Option<&mut Run> {
+        self.runs.iter_mut().find(|r| r.id == id)
+    }
+
+    /// Returns the id of the latest run that hasn't been reverted.
+    pub fn last_run(&self) -> Option<u32> {
+        self.runs.iter().rev().find(|r| !r.reverted).map(|r| r.id)
+    }
+
+    /// Starts recording a new run.
+    pub fn begin(&mut self,
+                 command: &str,
+                 sources: &[String],
+                 pending: &[PendingNote],
+                 started: u64)
+                 -> Transaction {
+        let id = self.next_id;
+        self.next_id += 1;
+        Transaction {
+            id: id,
+            command: command.to_string(),
+            sources: sources.to_vec(),
+            started: started,
+            before: BTreeMap::new(),
+            pending: pending.to_vec(),
+        }
+    }
+
+    /// Adds a run, or updates it if it was recorded before.
+    pub fn record(&mut self, run: Run) {
+        match self.runs.iter().position(|r| r.id == run.id) {
+            Some(index) => self.runs[index] = run,
+            None => self.runs.push(run),
+        }
+    }
+}
+
+/// The state of a term before a run changed it.
+struct TermState {
+    meanings: Option<Vec<ColMeaning>>,
+    records: Vec<Record>,
+}
+
+/// A run in progress, which remembers how the terms it touches looked.
+pub struct Transaction {
+    id: u32,
+    command: String,
+    sources: Vec<String>,
+    started: u64,
+    before: BTreeMap<String, TermState>,
+    /// The pending queue before the run.
+    pending: Vec<PendingNote>,
+}
+
+impl Transaction {
+    /// Remembers the term as it is before the run changes it the first time.
+    pub fn touch(&mut self, collection: &Collection, history: &History, term: &str) {
+        if self.before.contains_key(term) {
+            return;
+        }
+        let meanings = collection.meanings(term);
+        let state = TermState {
+            meanings: if meanings.is_empty() {
+                None
+            } else {
+                Some(meanings.clone())
+            },
+            records: history.records(term).to_vec(),
+        };
+        self.before.insert(term.to_string(), state);
+    }
+
+    /// Returns the run so far, leaving out the touched terms that didn't change.
+    pub fn to_run(&self, collection: &Collection, history: &History, complete: bool) -> Run {
+        let mut changes = Vec::new();
+        for (term, before) in &self.before {
+            let meanings = collection.meanings(term);
+            let after = if meanings.is_empty() {
+                None
+            } else {
+                Some(meanings.clone())
+            };
+            let records = history.records(term);
+            if after == before.meanings && records == &before.records[..] {
+                continue;
+            }
+            changes.push(TermChange {
+                term: term.clone(),
+                before: before.meanings.clone(),
+                after: after,
+                records_before: before.records.clone(),
+                records_after: records.to_vec(),
+                handled: None,
+            });
+        }
+        Run {
+            id: self.id,
+            command: self.command.clone(),
+            sources: self.sources.clone(),
+            started: self.started,
+            complete: complete,
+            reverted: false,
+            changes: changes,
+            deferred: history.pending()
+                .iter()
+                .filter(|&n| !self.pending.contains(n))
+                .cloned()
+                .collect(),
+            resolved: self.pending
+                .iter()
+                .filter(|&n| !history.pending().contains(n))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Reverts a run. Terms that have been changed since the run are left alone
+/// unless `force` is set, and are returned. Changes journaled without
+/// history records only check the meanings, and forget the notes they
+/// marked as handled.
+pub fn revert(run: &Run,
+              collection: &mut Collection,
+              history: &mut History,
+              force: bool)
+              -> Vec<String> {
+    let mut skipped = Vec::new();
+    for change in &run.changes {
+        let meanings = collection.meanings(&change.term);
+        let unchanged = match change.after {
+            Some(ref after) => after == meanings,
+            None => meanings.is_empty(),
+        } && (change.handled.is_some() ||
+              history.records(&change.term) == &change.records_after[..]);
+        if !unchanged && !force {
+            skipped.push(change.term.clone());
+            continue;
+        }
+        collection.set_meanings(&change.term, change.before.clone());
+        match change.handled {
+            Some(ref handled) => {
+                for meaning in handled {
+                    history.remove(&change.term, meaning);
+                }
+            }
+            None => history.set_records(&change.term, change.records_before.clone()),
+        }
+    }
+    for note in &run.deferred {
+        history.remove_pending(&note.term, &note.text);
+    }
+    for note in &run.resolved {
+        history.defer(note.clone());
+    }
+    skipped
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{Journal, revert};
+    use serde_json;
+    use collection::Collection;
+    use history::{History, Record, Outcome};
+    use testing::{meaning, note};
+
+    fn record(term: &str, text: &str) -> Record {
+        Record::new(&note(term, text), Outcome::Added, "test")
+    }
+
+    #[test]
+    fn undo() {
+        let mut col = Collection::new();
+        let mut his = History::new();
+        col.add_meaning("사과".to_string(), meaning("apple"), "one", None);
+        his.insert("사과".to_string(), record("사과", "apple"));
+
+        let mut journal = Journal::new();
+        let mut tx = journal.begin("integrate", &[], his.pending(), 0);
+        for &(term, text) in &[("사과", "apology"), ("학교", "school")] {
+            tx.touch(&col, &his, term);
+            col.add_meaning(term.to_string(), meaning(text), "two", Some(1));
+            his.insert(term.to_string(), record(term, text));
+        }
+        tx.touch(&col, &his, "사과");
+        let run = tx.to_run(&col, &his, false);
+        assert_eq!(2, run.changes.len());
+
+        // Changed after the run, so it is kept unless forced
+        col.add_meaning("학교".to_string(), meaning("college"), "three", None);
+        assert_eq!(vec!["학교".to_string()], revert(&run, &mut col, &mut his, false));
+        assert_eq!(1, col.meanings("사과").len());
+        assert!(!his.contains("사과", "apology"));
+        assert!(his.contains("사과", "apple"));
+        assert_eq!(2, col.meanings("학교").len());
+
+        revert(&run, &mut col, &mut his, true);
+        assert!(col.meanings("학교").is_empty());
+        assert!(!his.contains("학교", "school"));
+    }
+
+    #[test]
+    fn undo_v0() {
+        let mut col = Collection::new();
+        let mut his = History::new();
+        col.add_meaning("사과".to_string(), meaning("apple"), "one", None);
+        his.insert("사과".to_string(), record("사과", "apple"));
+        his.insert("사과".to_string(), record("사과", "apology"));
+        let after = serde_json::to_string(col.meanings("사과")).unwrap();
+        let json = format!(r#"{{"runs":[{{"id":0,"command":"integrate","sources":["one"],
+                               "started":0,"complete":true,"reverted":false,"deferred":[],
+                               "resolved":[],"changes":[{{"term":"사과","before":null,
+                               "after":{},"handled":["apple","apology"]}}]}}],
+                               "next_id":1}}"#,
+                           after);
+        let journal = Journal::from_json(&json).unwrap();
+        assert!(revert(&journal.runs()[0], &mut col, &mut his, false).is_empty());
+        assert!(col.meanings("사과").is_empty());
+        assert!(his.records("사과").is_empty());
+    }
+}
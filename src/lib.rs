@@ -0,0 +1,46 @@
+//! Memori keeps a collection of Korean vocabulary, integrated note by note
+//! from plain text sources, along with a history of how each note was
+//! handled and a review schedule for every meaning.
+#![feature(unicode)]
+
+extern crate std_unicode;
+extern crate hangeul2;
+extern crate regex;
+extern crate serde;
+extern crate toml;
+extern crate xml;
+
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+
+pub mod source;
+pub mod history;
+pub mod collection;
+pub mod integrate;
+pub mod session;
+pub mod prompt;
+pub mod journal;
+pub mod scheduler;
+pub mod search;
+pub mod answer;
+pub mod jamo;
+pub mod date;
+pub mod save;
+pub mod config;
+pub mod dictionary;
+pub mod error;
+mod migrate;
+mod bktree;
+#[cfg(test)]
+mod testing;
+
+pub use source::{Source, Note, Meaning};
+pub use collection::Collection;
+pub use history::History;
+pub use error::Error;
+pub use migrate::{MigrateError, COLLECTION_VERSION, HISTORY_VERSION, JOURNAL_VERSION,
+                  DICTIONARY_VERSION};
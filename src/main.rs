@@ -1,391 +1,1579 @@
-#![feature(unicode)]
-
-extern crate std_unicode;
-extern crate hangeul2;
-extern crate regex;
-
+extern crate memori;
 #[macro_use]
-extern crate lazy_static;
+extern crate clap;
+extern crate ctrlc;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 
-mod source;
-mod history;
-mod collection;
-
-use std::path::Path;
-use std::io::{self, Read, Write};
-use std::fs::File;
-use std::env;
+use std::io;
+use std::fs;
 use std::process::{self, Command};
-use std::error::Error;
-use source::{Source, Note};
-use collection::Collection;
-use history::History;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use memori::{date, jamo, journal, save, MigrateError, COLLECTION_VERSION, HISTORY_VERSION,
+             JOURNAL_VERSION};
+use memori::Error as FileError;
+use memori::config::{Config, ConfigError, Profile};
+use memori::dictionary::{Dictionary, DumpFormat, ImportError};
+use memori::source::{Source, SourceLoadError, Note, InvalidNote};
+use memori::collection::{Collection, ColMeaning, SourceAction};
+use memori::search::{MeaningIndex, TermIndex};
+use memori::history::{History, Record, Outcome, Filter};
+use memori::integrate::{self, ConflictPolicy, Decision, Action, Summary};
+use memori::prompt::{Prompter, PromptError, StdinPrompter, ScriptedPrompter, prompt_answer,
+                     print_definitions};
+use memori::journal::Journal;
+use memori::session::{self, Session, SessionError};
+use memori::scheduler::{Grade, NEW_PER_DAY};
+use memori::answer::{AnswerCheck, SyllableDiff};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-fn print_usage(errno: i32) -> Result<(), i32> {
-    println!("Usage: memori integrate <collection.json> <source.txt> [<source.txt> ...]");
-    if errno != 0 { Err(errno) } else { Ok(()) }
+/// Why a command failed. Each kind of failure has its own exit code, see
+/// `exit_code`.
+#[derive(Debug)]
+enum CliError {
+    /// An argument or request that can't be carried out.
+    Invalid(String),
+    Collection(String, FileError),
+    /// A collection that could not be upgraded to the current schema.
+    Migrate(String, MigrateError),
+    History(String, FileError),
+    Journal(String, FileError),
+    /// A decisions file that could not be read, with the reason.
+    Decisions(String, String),
+    Config(String, ConfigError),
+    Dictionary(String, FileError),
+    /// A dictionary dump that could not be imported.
+    Import(String, ImportError),
+    Source(String, SourceLoadError),
+    /// The number of invalid lines found by 'check'.
+    InvalidLines(usize),
+    /// A file that could not be copied to its backup before being rewritten.
+    Backup(String, String, io::Error),
+    /// A conflicting note (term, meaning) that could not be decided.
+    Decide(String, String, PromptError),
+    /// An answer that could not be read during a review.
+    Review(io::Error),
+    /// Ctrl-C was pressed, and the progress was saved.
+    Interrupted,
 }
 
-#[cfg(target_os = "macos")]
-fn open_in_dictionary(word: &str) {
-    let url = format!("dict://{}", word.replace(" ", "%20"));
-    let mut cmd = Command::new("open");
-    let _ = cmd.arg(&url).spawn();
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match *self {
+            CliError::Invalid(_) => 1,
+            CliError::Collection(_, FileError::Open(_)) => 2,
+            CliError::Collection(_, FileError::Read(_)) => 3,
+            CliError::Collection(_, FileError::Parse(_)) |
+            CliError::Migrate(..) => 4,
+            CliError::Source(..) |
+            CliError::InvalidLines(_) => 5,
+            CliError::Collection(_, FileError::Write(_)) |
+            CliError::History(_, FileError::Write(_)) |
+            CliError::Journal(_, FileError::Write(_)) |
+            CliError::Dictionary(_, FileError::Write(_)) |
+            CliError::Backup(..) => 6,
+            CliError::Decide(..) |
+            CliError::Review(_) => 7,
+            CliError::History(_, FileError::Open(_)) |
+            CliError::History(_, FileError::Read(_)) => 8,
+            CliError::History(_, FileError::Parse(_)) => 9,
+            CliError::Journal(..) |
+            CliError::Decisions(..) |
+            CliError::Dictionary(..) |
+            CliError::Import(..) => 10,
+            CliError::Config(..) => 11,
+            CliError::Interrupted => 130,
+        }
+    }
 }
 
-#[cfg(not(target_os = "macos"))]
-fn open_in_dictionary(word: &str) {}
-
-fn prompt_answer<T, F: FnMut(&str) -> Option<T>>(initial: Option<&str>,
-                                                 mut convertor: F)
-                                                 -> Result<T, io::Error> {
-    if let Some(initial) = initial {
-        if let Some(res) = convertor(initial) {
-            return Ok(res);
-        }
-    }
-    let mut input = String::new();
-    print!("> ");
-    loop {
-        io::stdout().flush()?;
-        io::stdin().read_line(&mut input)?;
-        input = input.trim().to_string();
-        if let Some(res) = convertor(&input) {
-            return Ok(res);
-        } else {
-            print!("! ");
+impl From<SessionError> for CliError {
+    fn from(err: SessionError) -> CliError {
+        match err {
+            SessionError::Collection(path, err) => CliError::Collection(path, err),
+            SessionError::History(path, err) => CliError::History(path, err),
+            SessionError::Journal(path, err) => CliError::Journal(path, err),
+            SessionError::Decide(term, meaning, err) => CliError::Decide(term, meaning, err),
+            SessionError::Interrupted => CliError::Interrupted,
         }
     }
 }
 
-#[derive(Debug)]
-enum Decision {
-    Reject,
-    Add(String),
-    Update(usize, String),
-}
-
-fn prompt_index(initial: &str, n_meanings: usize) -> Result<usize, i32> {
-    prompt_answer(if initial == "" { None } else { Some(initial) },
-                  |inp| if let Ok(index) = inp.parse::<usize>() {
-                      if index < n_meanings {
-                          Some(index)
-                      } else {
-                          println!("Index too big (>= {})", n_meanings);
-                          None
-                      }
-                  } else {
-                      None
-                  })
-        .map_err(|_| 6)
-}
-
-fn prompt_decision(collection: &Collection,
-                   note: &Note,
-                   source_title: &String)
-                   -> Result<Decision, i32> {
-    println!("New meaning found: ( from '{}' )", source_title);
-    println!("{} | {}", note.term, note.meaning.text);
-    println!("Existing meanings:");
-    let n_meanings = collection.meanings(&note.term).len();
-    for (i, meaning) in collection.meanings(&note.term).iter().enumerate() {
-        println!("{}) {} ['{}']",
-                 i,
-                 meaning.text,
-                 collection.title(meaning.source).unwrap());
-    }
-    println!("[a]dd [r]eplace [u]pdate [i]gnore");
-    print!("> ");
-    loop {
-        let mut input = String::new();
-        if let Err(err) = io::stdin().read_line(&mut input) {
-            println!("Error reading from stdin: '{}'", err.description());
-            return Err(7);
-        }
-        let answer = input.trim();
-        let (cmd, rem) = if let Some(index) = answer.find(" ") {
-            (&answer[..index], &answer[index + 1..])
-        } else {
-            (answer, "")
-        };
-        match cmd {
-            "a" => {
-                return Ok(Decision::Add(note.meaning.text.clone()));
-            }
-            "r" => {
-                println!("Please choose the index to replace ({} : {}):",
-                         0,
-                         n_meanings - 1);
-                let index = prompt_index(rem, n_meanings)?;
-                return Ok(Decision::Update(index, note.meaning.text.clone()));
-            }
-            "u" => {
-                println!("Please choose the index to update ({} : {}):",
-                         0,
-                         n_meanings - 1);
-                let (indextext, updatetext) = if let Some(index) = rem.find(" ") {
-                    (&rem[..index], Some(&rem[index..]))
-                } else {
-                    (rem, None)
-                };
-                let index = prompt_index(indextext, n_meanings)?;
-                let text = prompt_answer(updatetext, |inp| if inp != "" {
-                        Some(inp.to_string())
-                    } else {
-                        None
-                    }).map_err(|_| 6)?;
-                return Ok(Decision::Update(index, text));
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CliError::Invalid(ref msg) => write!(f, "{}", msg),
+            CliError::Collection(ref path, ref err) => {
+                write!(f, "Collection file ('{}'): {}", path, err)
+            }
+            CliError::Migrate(ref path, ref err) => {
+                write!(f, "Could not migrate '{}': '{}'", path, err)
+            }
+            CliError::History(ref path, ref err) => write!(f, "History file ('{}'): {}", path, err),
+            CliError::Journal(ref path, ref err) => write!(f, "Journal file ('{}'): {}", path, err),
+            CliError::Decisions(ref path, ref msg) => {
+                write!(f, "Decisions file ('{}'): {}", path, msg)
+            }
+            CliError::Config(ref path, ref err) => write!(f, "Config file ('{}'): {}", path, err),
+            CliError::Dictionary(ref path, ref err) => {
+                write!(f, "Dictionary file ('{}'): {}", path, err)
+            }
+            CliError::Import(ref path, ref err) => {
+                write!(f, "Could not import '{}': {}", path, err)
             }
-            "i" => {
-                return Ok(Decision::Reject);
+            CliError::Source(ref path, SourceLoadError::Io(ref err)) => {
+                write!(f, "Could not read source at '{}': {}", path, err)
             }
-            _ => {
-                println!("Unrecognized command: '{}'", cmd);
+            CliError::Source(ref path, SourceLoadError::InvalidNote(_)) => {
+                write!(f,
+                       "Source '{}' has an invalid line, run 'memori check' to list every one",
+                       path)
             }
+            CliError::InvalidLines(n_invalid) => write!(f, "Found {} invalid line(s)", n_invalid),
+            CliError::Backup(ref path, ref backup, ref err) => {
+                write!(f, "Could not back up '{}' to '{}': '{}'", path, backup, err)
+            }
+            CliError::Decide(ref term, ref meaning, ref err) => {
+                write!(f, "Could not decide ({} | {}): {}", term, meaning, err)
+            }
+            CliError::Review(ref err) => write!(f, "Could not read the answer: {}", err),
+            CliError::Interrupted => write!(f, "Interrupted, progress has been saved"),
         }
     }
 }
 
-fn load_collection(colpath: &str) -> Result<Collection, i32> {
-    let cpath = Path::new(colpath);
-    Ok(if !cpath.exists() {
-        Collection::new()
+/// The dictionary command used when none is set in the config file.
+#[cfg(target_os = "macos")]
+const DEFAULT_DICTIONARY_COMMAND: Option<&'static str> = Some("open dict://{term}");
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_DICTIONARY_COMMAND: Option<&'static str> = None;
+
+/// Runs a dictionary command for a term (see `DictionarySettings`). In
+/// words that are URLs, the spaces of the term are written as '%20'.
+fn open_in_dictionary(template: &str, term: &str, wait: bool) {
+    let words = template.split_whitespace()
+        .map(|word| if word.contains("://") {
+            word.replace("{term}", &term.replace(" ", "%20"))
+        } else {
+            word.replace("{term}", term)
+        })
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        return;
+    }
+    let mut cmd = Command::new(&words[0]);
+    cmd.args(&words[1..]);
+    let res = if wait {
+        cmd.status().map(|_| ())
     } else {
-        let mut file = match File::open(&cpath) {
-            Ok(file) => file,
-            Err(err) => {
-                println!("Could not open collection file : '{}'", err.description());
-                return Err(2);
+        cmd.spawn().map(|_| ())
+    };
+    if let Err(err) = res {
+        println!("Could not run the dictionary command '{}': {}", words[0], err);
+    }
+}
+
+/// Asks on the terminal, after looking the term up in the dictionary.
+struct TerminalPrompter {
+    /// The dictionary command, and whether to wait for it.
+    command: Option<(String, bool)>,
+    /// The local dictionary file, until it is loaded for the first conflict.
+    dictionary_file: Option<String>,
+    stdin: StdinPrompter,
+}
+
+impl TerminalPrompter {
+    fn new(config: &Config) -> TerminalPrompter {
+        let settings = &config.dictionary;
+        let command = settings.command
+            .clone()
+            .or(DEFAULT_DICTIONARY_COMMAND.map(|command| command.to_string()));
+        TerminalPrompter {
+            command: command.map(|command| (command, settings.wait)),
+            dictionary_file: config.dictionary_file(),
+            stdin: StdinPrompter::default(),
+        }
+    }
+}
+
+/// Loads the local dictionary, if one is set in the config file.
+fn load_dictionary(config: &Config) -> Result<Option<Dictionary>, CliError> {
+    match config.dictionary_file() {
+        Some(path) => {
+            match Dictionary::load(&path) {
+                Ok(dictionary) => Ok(Some(dictionary)),
+                Err(err) => Err(CliError::Dictionary(path, err)),
             }
-        };
-        let mut json = String::new();
-        if let Err(err) = file.read_to_string(&mut json) {
-            println!("Could not read collection file: '{}'", err.description());
-            return Err(3);
         }
-        match Collection::from_json(&json) {
-            Ok(col) => col,
-            Err(err) => {
-                println!("Could not parse collection JSON: '{}'", err.description());
-                return Err(4);
+        None => Ok(None),
+    }
+}
+
+/// Adds the words of dictionary dumps to an index, which is created if it
+/// doesn't exist yet.
+fn import_dictionary(index_path: &str,
+                     dump_paths: &[String],
+                     format: Option<DumpFormat>)
+                     -> Result<(), CliError> {
+    let mut dictionary = match save::read(index_path) {
+        Ok(Some(text)) => {
+            if !Dictionary::is_index(&text) {
+                let msg = format!("'{}' is a list of definitions, not an index to import into",
+                                  index_path);
+                return Err(CliError::Invalid(msg));
+            }
+            match Dictionary::from_text(&text) {
+                Ok(dictionary) => dictionary,
+                Err(err) => {
+                    return Err(CliError::Dictionary(index_path.to_string(), FileError::Parse(err)))
+                }
             }
         }
-    })
+        Ok(None) => Dictionary::default(),
+        Err(err) => return Err(CliError::Dictionary(index_path.to_string(), err)),
+    };
+    for dump_path in dump_paths {
+        let format = match format.or(DumpFormat::guess(dump_path)) {
+            Some(format) => format,
+            None => {
+                let msg = format!("Unknown kind of dump: '{}', use --format", dump_path);
+                return Err(CliError::Invalid(msg));
+            }
+        };
+        let n_entries = dictionary.import(dump_path, format)
+            .map_err(|err| CliError::Import(dump_path.clone(), err))?;
+        println!("Imported {} entries from '{}'", n_entries, dump_path);
+    }
+    dictionary.save(index_path).map_err(|err| CliError::Dictionary(index_path.to_string(), err))?;
+    println!("Saved the dictionary ({} terms) to '{}'", dictionary.len(), index_path);
+    Ok(())
+}
+
+impl Prompter for TerminalPrompter {
+    fn decide(&mut self,
+              collection: &Collection,
+              note: &Note,
+              source_title: &str,
+              edited: Option<usize>)
+              -> Result<Decision, PromptError> {
+        if let Some(path) = self.dictionary_file.take() {
+            match Dictionary::load(&path) {
+                Ok(dictionary) => self.stdin.dictionary = Some(dictionary),
+                Err(err) => println!("Warning: {}", CliError::Dictionary(path, err)),
+            }
+        }
+        if let Some((ref command, wait)) = self.command {
+            open_in_dictionary(command, &note.term, wait);
+        }
+        self.stdin.decide(collection, note, source_title, edited)
+    }
+}
+
+/// Returns what decides conflicts: the decisions file if one is given, or
+/// else the terminal.
+fn conflict_prompter(decisions: Option<&str>, config: &Config) -> Result<Box<Prompter>, CliError> {
+    match decisions {
+        Some(path) => Ok(Box::new(load_decisions(path)?)),
+        None => Ok(Box::new(TerminalPrompter::new(config))),
+    }
+}
+
+/// Reads a file of decisions for 'integrate --decisions'.
+fn load_decisions(path: &str) -> Result<ScriptedPrompter, CliError> {
+    let text = match save::read(path) {
+        Ok(Some(text)) => text,
+        Ok(None) => {
+            return Err(CliError::Decisions(path.to_string(), "the file does not exist".into()))
+        }
+        Err(err) => return Err(CliError::Decisions(path.to_string(), err.to_string())),
+    };
+    ScriptedPrompter::parse(&text).map_err(|msg| CliError::Decisions(path.to_string(), msg))
+}
+
+/// Returns the number of terminal columns the text takes up.
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| match c as u32 {
+            0x1100...0x115F | 0x2E80...0x303E | 0x3041...0x33FF | 0x3400...0x4DBF |
+            0x4E00...0x9FFF | 0xAC00...0xD7A3 | 0xF900...0xFAFF | 0xFF00...0xFF60 => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Prints an invalid source line in the style of a compiler diagnostic.
+fn print_invalid_note(invalid: &InvalidNote) {
+    let prefix = invalid.line.chars().take(invalid.column - 1).collect::<String>();
+    let gutter = " ".repeat(invalid.lineno.to_string().len());
+    println!("error: {}", invalid.reason);
+    println!("{}--> {}:{}:{}", gutter, invalid.file, invalid.lineno, invalid.column);
+    println!("{} |", gutter);
+    println!("{} | {}", invalid.lineno, invalid.line);
+    println!("{} | {}^", gutter, " ".repeat(display_width(&prefix)));
 }
 
-fn load_history(hispath: &str) -> Result<History, i32> {
-    let path = Path::new(hispath);
-    Ok(if !path.exists() {
-        History::new()
+fn print_meaning(term: &str, meaning: &ColMeaning) {
+    match meaning.symbol {
+        Some(ref symbol) => println!("{} {} | {}", term, symbol, meaning.text),
+        None => println!("{} | {}", term, meaning.text),
+    }
+    if let Some(ref example) = meaning.example {
+        println!("   \"{}\"", example);
+    }
+}
+
+/// Shows the term, then the meaning, and asks how well it was remembered.
+/// Returns None if the user wants to stop reviewing.
+fn review_card(collection: &Collection,
+               term: &str,
+               index: usize)
+               -> Result<Option<Grade>, io::Error> {
+    let n_meanings = collection.meanings(term).len();
+    println!("{}", term);
+    if n_meanings > 1 {
+        println!("(meaning {} of {})", index + 1, n_meanings);
+    }
+    println!("Press enter to show the answer");
+    prompt_answer(None, |_| Some(()))?;
+    print_meaning(term, &collection.meanings(term)[index]);
+    println!("[1] again [2] hard [3] good [4] easy [q]uit");
+    prompt_answer(None, |inp| if inp == "q" {
+        Some(None)
     } else {
-        let mut file = File::open(&path).expect("Could not open file");
-        let mut json = String::new();
-        file.read_to_string(&mut json).expect("Could not read file");
-        History::from_json(&json).expect("Could not parse JSON to struct")
+        Grade::from_str(inp).map(Some)
     })
 }
 
-fn save_history(history: &History, hispath: &str) -> Result<(), i32> {
-    let serialized = serde_json::to_string(&history).unwrap();
-    let mut outfile = match File::create(&hispath) {
-        Ok(f) => f,
-        Err(err) => {
-            println!("Could not open history for writing ('{}'): '{}'",
-                     hispath,
-                     err.description());
-            return Err(6);
+/// Shows the meaning and grades the Korean term typed in by the user.
+/// Returns None if the user wants to stop reviewing.
+fn review_typed(collection: &Collection,
+                term: &str,
+                index: usize)
+                -> Result<Option<Grade>, io::Error> {
+    let ref meaning = collection.meanings(term)[index];
+    match meaning.symbol {
+        Some(ref symbol) => println!("{} ({})", meaning.text, symbol),
+        None => println!("{}", meaning.text),
+    }
+    println!("Type the Korean term ([q]uit)");
+    let typed = prompt_answer(None, |inp| Some(inp.to_string()))?;
+    if typed == "q" {
+        return Ok(None);
+    }
+    let check = AnswerCheck::new(term, &typed);
+    if check.is_correct() {
+        println!("Correct!");
+    } else {
+        println!("Expected: {}", term);
+        for diff in &check.diffs {
+            match *diff {
+                SyllableDiff::Same(ch) => println!("  {} = {}", ch, ch),
+                SyllableDiff::Changed { expected, typed, ref jamo } => {
+                    let details = jamo.iter()
+                        .map(|d| {
+                            format!("{} {} not {}",
+                                    d.part,
+                                    d.expected.map(|c| c.to_string()).unwrap_or("(none)".into()),
+                                    d.typed.map(|c| c.to_string()).unwrap_or("(none)".into()))
+                        })
+                        .collect::<Vec<_>>();
+                    if details.is_empty() {
+                        println!("  {} ~ {}", expected, typed);
+                    } else {
+                        println!("  {} ~ {}  ({})", expected, typed, details.join(", "));
+                    }
+                }
+                SyllableDiff::Missing(ch) => println!("  {} - (missing)", ch),
+                SyllableDiff::Extra(ch) => println!("  + {} (extra)", ch),
+            }
+        }
+        println!("{} jamo off, {:.0}% right", check.distance, check.credit() * 100.0);
+    }
+    print_meaning(term, meaning);
+    let grade = check.grade();
+    println!("=> {:?}", grade);
+    Ok(Some(grade))
+}
+
+fn load_collection(colpath: &str) -> Result<Collection, CliError> {
+    Collection::load(colpath).map_err(|err| CliError::Collection(colpath.to_string(), err))
+}
+
+/// Rewrites a collection, history or journal saved with an older schema in
+/// the current one, keeping the original as '<file>.v<version>.bak'.
+/// `error` names the kind of file in errors.
+fn migrate_file<T, M, W, E>(path: &str,
+                            current: u32,
+                            migrate: M,
+                            write: W,
+                            error: E)
+                            -> Result<(), CliError>
+    where M: Fn(&str) -> Result<(T, u32), MigrateError>,
+          W: Fn(&T, &str) -> Result<(), FileError>,
+          E: Fn(String, FileError) -> CliError
+{
+    let json = match save::read(path) {
+        Ok(Some(json)) => json,
+        Ok(None) => {
+            let err = io::Error::new(io::ErrorKind::NotFound, "the file does not exist");
+            return Err(error(path.to_string(), FileError::Open(err)));
         }
+        Err(err) => return Err(error(path.to_string(), err)),
+    };
+    let (migrated, version) = match migrate(&json) {
+        Ok(res) => res,
+        Err(err) => return Err(CliError::Migrate(path.to_string(), err)),
     };
-    if let Err(err) = outfile.write_all(serialized.as_bytes()) {
-        println!("Could not write to history file ('{}'): '{}'",
-                 hispath,
-                 err.description());
-        return Err(6);
+    if version == current {
+        println!("'{}' is up to date (version {})", path, version);
+        return Ok(());
     }
+    let backup = format!("{}.v{}.bak", path, version);
+    if let Err(err) = fs::copy(path, &backup) {
+        return Err(CliError::Backup(path.to_string(), backup, err));
+    }
+    write(&migrated, path).map_err(|err| error(path.to_string(), err))?;
+    println!("Migrated '{}' from version {} to {} (the original is in '{}')",
+             path,
+             version,
+             current,
+             backup);
+    Ok(())
+}
+
+/// Migrates collections along with their journals, and a history.
+fn migrate_files(colpaths: &[String], hispath: Option<&str>) -> Result<(), CliError> {
+    for colpath in colpaths {
+        migrate_file(colpath,
+                     COLLECTION_VERSION,
+                     Collection::migrate_json,
+                     |collection, path| collection.save(path, false),
+                     CliError::Collection)?;
+        // There is only a journal once the collection has been changed
+        let jourpath = Journal::path_for(colpath);
+        if Path::new(&jourpath).exists() {
+            migrate_file(&jourpath,
+                         JOURNAL_VERSION,
+                         Journal::migrate_json,
+                         |journal, path| journal.save(path),
+                         CliError::Journal)?;
+        }
+    }
+    if let Some(hispath) = hispath {
+        migrate_file(hispath,
+                     HISTORY_VERSION,
+                     History::migrate_json,
+                     |history, path| history.save(path, false),
+                     CliError::History)?;
+    }
+    Ok(())
+}
+
+fn load_history(hispath: &str) -> Result<History, CliError> {
+    History::load(hispath).map_err(|err| CliError::History(hispath.to_string(), err))
+}
+
+fn write_history(history: &History, hispath: &str, backup: bool) -> Result<(), CliError> {
+    history.save(hispath, backup).map_err(|err| CliError::History(hispath.to_string(), err))
+}
+
+fn save_history(history: &History, hispath: &str) -> Result<(), CliError> {
+    write_history(history, hispath, true)?;
     println!("Saved history, woohoo!");
     Ok(())
 }
 
-fn save_collection(collection: &Collection, colpath: &str) -> Result<(), i32> {
-    let serialized = serde_json::to_string(&collection).unwrap();
-    let mut outfile = match File::create(&colpath) {
-        Ok(f) => f,
-        Err(err) => {
-            println!("Could not open collection for writing ('{}'): '{}'",
-                     colpath,
-                     err.description());
-            return Err(6);
+fn write_collection(collection: &Collection,
+                    colpath: &str,
+                    backup: bool)
+                    -> Result<(), CliError> {
+    collection.save(colpath, backup).map_err(|err| CliError::Collection(colpath.to_string(), err))
+}
+
+fn save_collection(collection: &Collection, colpath: &str) -> Result<(), CliError> {
+    write_collection(collection, colpath, true)?;
+    println!("Saved collection, yay!");
+    Ok(())
+}
+
+fn load_journal(jourpath: &str) -> Result<Journal, CliError> {
+    Journal::load(jourpath).map_err(|err| CliError::Journal(jourpath.to_string(), err))
+}
+
+fn write_journal(journal: &Journal, jourpath: &str) -> Result<(), CliError> {
+    journal.save(jourpath).map_err(|err| CliError::Journal(jourpath.to_string(), err))
+}
+
+fn print_action(action: Action, note: &Note) {
+    use Action::*;
+    let (term, text) = (&note.term, &note.meaning.text);
+    match action {
+        Skipped => println!("- Skipping ({} | {})", term, text),
+        Known => println!("- Already known ({} | {})", term, text),
+        Added => println!("Adding ({} | {})!", term, text),
+        Conflict => println!("? Conflict ({} | {})", term, text),
+        AddedAlongside => println!("Adding new ({} | {})!", term, text),
+        Ignored => println!("Rejected ({} | {})", term, text),
+        Replaced => println!("Replaced ({} | {})", term, text),
+        Edited => println!("Edited ({} | {})", term, text),
+        Deferred => println!("Deferred ({} | {})", term, text),
+    }
+}
+
+/// Prints what happened to the notes of a source.
+fn print_summary(summary: &Summary, title: &str) {
+    println!("'{}': {} added, {} skipped", title, summary.added, summary.skipped);
+    if summary.edited > 0 {
+        println!("  {} edited line(s) updated in place", summary.edited);
+    }
+    if summary.unresolved > 0 {
+        println!("  {} conflict(s) would need a decision", summary.unresolved);
+    }
+    println!("  conflicts: {} added, {} ignored, {} replaced, {} deferred",
+             summary.added_alongside,
+             summary.ignored,
+             summary.replaced,
+             summary.deferred);
+}
+
+/// A note in the report of a dry run.
+#[derive(Debug, Serialize)]
+struct PlannedNote {
+    action: &'static str,
+    term: String,
+    meaning: String,
+    line: usize,
+    /// The meanings the note conflicts with.
+    existing: Vec<String>,
+    /// The meaning from an earlier version of the note's line.
+    edits: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SourcePlan {
+    path: String,
+    title: String,
+    notes: Vec<PlannedNote>,
+}
+
+struct IntegrateOptions {
+    policy: ConflictPolicy,
+    /// Only report what would be done, without prompting or saving.
+    dry_run: bool,
+    /// Print the report of a dry run as JSON.
+    json: bool,
+    /// A file of decisions to use instead of prompting.
+    decisions: Option<String>,
+}
+
+fn integrate(colpath: &str,
+             hispath: &str,
+             source_paths: &[String],
+             options: &IntegrateOptions,
+             prompter: &mut Prompter)
+             -> Result<(), CliError> {
+    let mut session = Session::load(colpath, hispath, options.dry_run)?;
+    session.begin("integrate", source_paths)?;
+    let mut summaries = Vec::new();
+    let mut plans = Vec::new();
+    if !options.dry_run {
+        handle_interrupts();
+    }
+
+    for source_path in source_paths {
+        let source = match Source::load(source_path) {
+            Ok(s) => s,
+            Err(err) => {
+                if let SourceLoadError::InvalidNote(ref invalid) = err {
+                    print_invalid_note(invalid);
+                }
+                session.save()?;
+                return Err(CliError::Source(source_path.clone(), err));
+            }
+        };
+        // Dry runs leave the conflicts open instead of prompting
+        let prompter = if options.dry_run && options.decisions.is_none() {
+            None
+        } else {
+            Some(&mut *prompter)
+        };
+        let mut planned = Vec::new();
+        let summary = integrate::integrate_source(&mut session,
+                                                  &source,
+                                                  options.policy,
+                                                  prompter,
+                                                  |handled, collection| {
+            let note = handled.note;
+            let existing = if handled.action == Action::Conflict {
+                collection.meanings(&note.term).iter().map(|m| m.text.clone()).collect()
+            } else {
+                Vec::new()
+            };
+            if !options.json {
+                print_action(handled.action, note);
+                for (i, text) in existing.iter().enumerate() {
+                    println!("    {}) {}", i, text);
+                }
+                if let Some(ref text) = handled.edits {
+                    println!("    (line {} used to be '{}')", note.lineno, text);
+                }
+            }
+            planned.push(PlannedNote {
+                action: handled.action.name(),
+                term: note.term.clone(),
+                meaning: note.meaning.text.clone(),
+                line: note.lineno,
+                existing: existing,
+                edits: handled.edits.clone(),
+            });
+        })?;
+        summaries.push((source.title.clone(), summary));
+        plans.push(SourcePlan {
+            path: source_path.clone(),
+            title: source.title.clone(),
+            notes: planned,
+        });
+    }
+
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&plans).unwrap());
+        return Ok(());
+    }
+    for &(ref title, ref summary) in &summaries {
+        print_summary(summary, title);
+    }
+    if options.dry_run {
+        println!("Dry run: nothing was saved");
+        return Ok(());
+    }
+    if !session.history.pending().is_empty() {
+        println!("{} conflict(s) pending, run 'memori resolve' to go through them",
+                 session.history.pending().len());
+    }
+    finish(&mut session)
+}
+
+/// Makes Ctrl-C stop a session after saving instead of killing it.
+fn handle_interrupts() {
+    let res = ctrlc::set_handler(|| {
+        if session::interrupt() {
+            // Everything is saved before prompting
+            println!("\nInterrupted, progress has been saved");
+            process::exit(130);
         }
-    };
-    if let Err(err) = outfile.write_all(serialized.as_bytes()) {
-        println!("Could not write to collection file ('{}'): '{}'",
-                 colpath,
-                 err.description());
-        return Err(6);
+    });
+    if let Err(err) = res {
+        println!("Warning: Ctrl-C will not save progress: {:?}", err);
     }
+}
+
+/// Saves a session that got to the end.
+fn finish(session: &mut Session) -> Result<(), CliError> {
+    session.finish()?;
     println!("Saved collection, yay!");
+    println!("Saved history, woohoo!");
     Ok(())
 }
 
+/// Goes through the conflicts deferred by earlier integrate runs, asking
+/// the prompter about each.
+fn resolve(colpath: &str, hispath: &str, prompter: &mut Prompter) -> Result<(), CliError> {
+    let mut session = Session::load(colpath, hispath, false)?;
+    session.begin("resolve", &[])?;
+    handle_interrupts();
+    let summary = integrate::resolve_pending(&mut session, prompter, |handled, _| {
+        print_action(handled.action, handled.note)
+    })?;
+    print_summary(&summary, "pending");
+    finish(&mut session)
+}
 
+/// Reads the --term, --source, --decision, --since and --until options.
+fn parse_filter(args: &ArgMatches) -> Filter {
+    // The dates and decision have been validated by the app
+    let day = |name| args.value_of(name).map(|value| date::parse_day(value).expect("INVARIANT!"));
+    Filter {
+        term: args.value_of("term").map(|term| term.to_string()),
+        source: args.value_of("source").map(|source| source.to_string()),
+        outcome: args.value_of("decision").map(|name| Outcome::from_str(name).expect("INVARIANT!")),
+        since: day("since"),
+        until: day("until"),
+    }
+}
 
+/// Prints a history record on one line.
+fn print_record(term: &str, record: &Record) {
+    let mut line = match record.timestamp {
+        Some(timestamp) => date::format_timestamp(timestamp),
+        None => "(undated)".to_string(),
+    };
+    line.push_str(&format!(" {:<15} {} | {}",
+                           record.outcome.map_or("?", |o| o.name()),
+                           term,
+                           record.meaning));
+    if let Some(ref replacement) = record.replacement {
+        line.push_str(&format!(" -> {}", replacement));
+    }
+    if let Some(ref source) = record.source {
+        line.push_str(&format!(" ['{}'", source));
+        if let Some(lineno) = record.lineno {
+            line.push_str(&format!(" line {}", lineno));
+        }
+        line.push_str("]");
+    }
+    println!("{}", line);
+}
+
+/// Prints where and when a revisited note was rejected.
+fn print_rejection(record: &Record) {
+    println!("(line {} of '{}', rejected on {})",
+             record.lineno.map_or("?".to_string(), |n| n.to_string()),
+             record.source.as_ref().map_or("?", |s| s.as_str()),
+             record.timestamp.map_or("?".to_string(), date::format_timestamp));
+}
+
+/// Goes through rejected notes again, so that they can still be added.
+fn revisit(colpath: &str,
+           hispath: &str,
+           filter: &Filter,
+           prompter: &mut Prompter)
+           -> Result<(), CliError> {
+    let mut session = Session::load(colpath, hispath, false)?;
+    session.begin("revisit", &[])?;
+    handle_interrupts();
+    let summary = integrate::revisit_rejected(&mut session,
+                                              filter,
+                                              prompter,
+                                              print_rejection,
+                                              |handled, _| {
+                                                  print_action(handled.action, handled.note)
+                                              })?;
+    print_summary(&summary, "rejected");
+    finish(&mut session)
+}
 
-fn run() -> Result<(), i32> {
-    let args = env::args().skip(1).collect::<Vec<_>>();
-    if args.len() < 1 {
-        print_usage(1)?;
+/// The most matches shown by searches.
+const MAX_MATCHES: usize = 20;
+/// The most misspelling candidates shown by lookups.
+const MAX_FUZZY_MATCHES: usize = 5;
+
+/// Prints the meanings of a term, with their full provenance if verbose.
+fn print_meanings(collection: &Collection, term: &str, verbose: bool) {
+    for (i, meaning) in collection.meanings(term).iter().enumerate() {
+        let title = collection.title(meaning.source).map_or("?", |t| t.as_str());
+        println!("{}) {} ['{}']", i, meaning.text, title);
+        if let Some(ref example) = meaning.example {
+            println!("   \"{}\"", example);
+        }
+        if !verbose {
+            continue;
+        }
+        for change in &meaning.provenance {
+            let action = match change.action {
+                SourceAction::Add => "added by",
+                SourceAction::Confirm => "confirmed by",
+                SourceAction::Replace => "replaced by",
+                SourceAction::Update => "updated from",
+            };
+            let title = collection.title(change.source).map_or("?", |t| t.as_str());
+            let mut line = format!("     {} '{}'", action, title);
+            if let Some(lineno) = change.lineno {
+                line.push_str(&format!(" line {}", lineno));
+            }
+            if let Some(timestamp) = change.timestamp {
+                line.push_str(&format!(" on {}", date::format_timestamp(timestamp)));
+            }
+            println!("{}", line);
+        }
     }
-    let ref cmd = args[0];
-    match cmd.as_str() {
-        "integrate" => {
-            if args.len() < 3 {
-                print_usage(1)?;
+}
+
+/// The format of decision files, for the help of the commands taking them.
+macro_rules! decisions_help {
+    () => {
+        "Decision files have a line per note: <term> TAB <meaning> TAB <decision>, where
+the decision is add, ignore, defer, edit, replace TAB <index> or
+update TAB <index> TAB <new text>. Lines starting with '#' are comments."
+    }
+}
+
+const INTEGRATE_HELP: &'static str = concat!("\
+Conflict policies:
+    prompt            ask about every conflict (default)
+    add               add the note next to the other meanings
+    ignore            reject the note
+    replace-newest    replace the newest meaning of the term
+    defer             queue the note for 'memori resolve'
+add and replace-newest update the meaning of an edited source line in place.
+
+The collection and history used to be the first two arguments; give them with
+--collection and --history, or set them in the config file, instead.
+
+",
+                                             decisions_help!());
+
+const CONFIG_HELP: &'static str = "\
+The collection and history files can be set in the config file
+(~/.config/memori/config.toml), either at the top or in a profile, along with
+how the terms of conflicting notes are looked up:
+    collection = \"~/korean/collection.json\"
+    history = \"~/korean/history.json\"
+
+    [profiles.test]
+    collection = \"test.json\"
+
+    [dictionary]
+    # Run for every conflict ('open dict://{term}' on macOS by default)
+    command = \"goldendict {term}\"
+    # Whether to wait for the command, if it prints to the terminal
+    wait = false
+    # Lines of <term> TAB <definition>, or an index made by 'memori dict import',
+    # shown with conflicts and by lookup
+    file = \"~/korean/dictionary.tsv\"
+
+Exit codes:
+    1      invalid arguments
+    2/3/4  the collection could not be opened/read/parsed
+    5      invalid source
+    6      a file could not be saved
+    7      no decision could be made
+    8/9    the history could not be read/parsed
+    10     the journal, decisions or dictionary file could not be read
+    11     the config file could not be read
+    130    interrupted (after saving)";
+
+fn collection_arg() -> Arg<'static, 'static> {
+    Arg::with_name("collection")
+        .long("collection")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("The collection file, instead of the one in the config file")
+}
+
+fn history_arg() -> Arg<'static, 'static> {
+    Arg::with_name("history")
+        .long("history")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("The history file, instead of the one in the config file")
+}
+
+fn profile_arg() -> Arg<'static, 'static> {
+    Arg::with_name("profile")
+        .long("profile")
+        .short("p")
+        .takes_value(true)
+        .value_name("NAME")
+        .help("Uses the files of a profile in the config file")
+}
+
+fn decisions_arg() -> Arg<'static, 'static> {
+    Arg::with_name("decisions")
+        .long("decisions")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("Takes the decisions on conflicts from a file instead of asking")
+}
+
+/// Adds the options choosing the files a subcommand works on.
+fn with_files(cmd: App<'static, 'static>,
+              collection: bool,
+              history: bool)
+              -> App<'static, 'static> {
+    let cmd = cmd.arg(profile_arg());
+    let cmd = if collection { cmd.arg(collection_arg()) } else { cmd };
+    if history { cmd.arg(history_arg()) } else { cmd }
+}
+
+/// The --term, --source, --since and --until options.
+fn filter_args() -> Vec<Arg<'static, 'static>> {
+    vec![Arg::with_name("term").long("term").takes_value(true).help("Only notes of this term"),
+         Arg::with_name("source")
+             .long("source")
+             .takes_value(true)
+             .value_name("TITLE")
+             .help("Only notes from this source"),
+         Arg::with_name("since")
+             .long("since")
+             .takes_value(true)
+             .value_name("YYYY-MM-DD")
+             .validator(validate_date)
+             .help("Only notes handled on or after this day"),
+         Arg::with_name("until")
+             .long("until")
+             .takes_value(true)
+             .value_name("YYYY-MM-DD")
+             .validator(validate_date)
+             .help("Only notes handled on or before this day")]
+}
+
+fn validate_date(value: String) -> Result<(), String> {
+    match date::parse_day(&value) {
+        Some(_) => Ok(()),
+        None => Err(format!("invalid date (expected YYYY-MM-DD): '{}'", value)),
+    }
+}
+
+fn validate_index(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("invalid index: '{}'", value)),
+    }
+}
+
+fn validate_count(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("invalid number: '{}'", value)),
+    }
+}
+
+fn validate_run(value: String) -> Result<(), String> {
+    match value.trim_left_matches('#').parse::<u32>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("invalid run: '{}'", value)),
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    let integrate = SubCommand::with_name("integrate")
+        .about("Adds the notes of sources to the collection")
+        .arg(Arg::with_name("on-conflict")
+            .long("on-conflict")
+            .takes_value(true)
+            .value_name("POLICY")
+            .possible_values(&["prompt", "add", "ignore", "replace-newest", "defer"])
+            .default_value("prompt")
+            .help("What to do with notes whose term has other meanings; add and \
+                   replace-newest edit the meaning of an edited line instead"))
+        .arg(decisions_arg())
+        .arg(Arg::with_name("dry-run")
+            .long("dry-run")
+            .help("Only reports what would be done, without asking or saving"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .requires("dry-run")
+            .help("Prints the report of a dry run as JSON"))
+        .arg(Arg::with_name("sources").value_name("SOURCE").multiple(true).required(true))
+        .after_help(INTEGRATE_HELP);
+    let resolve = SubCommand::with_name("resolve")
+        .about("Goes through the conflicts deferred by earlier runs")
+        .arg(decisions_arg())
+        .after_help(decisions_help!());
+    let lookup = SubCommand::with_name("lookup")
+        .about("Shows the meanings of a term, or of the terms with its initials (초성) \
+                or a similar spelling")
+        .arg(Arg::with_name("verbose")
+            .long("verbose")
+            .short("v")
+            .help("Also shows where each meaning came from"))
+        .arg(Arg::with_name("term").value_name("TERM").required(true));
+    let rlookup = SubCommand::with_name("rlookup")
+        .about("Finds terms by the text of their meanings")
+        .arg(Arg::with_name("text").value_name("TEXT").multiple(true).required(true));
+    let remove = SubCommand::with_name("remove")
+        .about("Removes a meaning of a term")
+        .arg(Arg::with_name("forget")
+            .long("forget")
+            .help("Forgets the note, so that the next integrate offers it again"))
+        .arg(Arg::with_name("term").value_name("TERM").required(true))
+        .arg(Arg::with_name("index").value_name("INDEX").required(true).validator(validate_index));
+    let rename = SubCommand::with_name("rename")
+        .about("Renames a term")
+        .arg(Arg::with_name("term").value_name("TERM").required(true))
+        .arg(Arg::with_name("new-term").value_name("NEW_TERM").required(true));
+    let merge = SubCommand::with_name("merge")
+        .about("Moves the meanings of a term to another term")
+        .arg(Arg::with_name("term").value_name("TERM").required(true))
+        .arg(Arg::with_name("new-term").value_name("INTO_TERM").required(true));
+    let unimport = SubCommand::with_name("unimport")
+        .about("Undoes everything a source brought into the collection")
+        .arg(Arg::with_name("title").value_name("TITLE").required(true));
+    let history = SubCommand::with_name("history")
+        .about("Lists how notes were handled")
+        .args(&filter_args())
+        .arg(Arg::with_name("decision")
+            .long("decision")
+            .takes_value(true)
+            .possible_values(&["added",
+                               "known",
+                               "rejected",
+                               "added-alongside",
+                               "replaced",
+                               "updated",
+                               "edited"])
+            .help("Only notes handled this way"));
+    let revisit = SubCommand::with_name("revisit")
+        .about("Goes through rejected notes again")
+        .args(&filter_args());
+    let log = SubCommand::with_name("log").about("Lists the runs that changed the collection");
+    let undo = SubCommand::with_name("undo")
+        .about("Reverts a run, the last one by default")
+        .arg(Arg::with_name("force")
+            .long("force")
+            .help("Also reverts terms that have been changed since the run"))
+        .arg(Arg::with_name("run").value_name("RUN").validator(validate_run));
+    let check = SubCommand::with_name("check")
+        .about("Lists the invalid lines of sources")
+        .arg(Arg::with_name("sources").value_name("SOURCE").multiple(true).required(true));
+    let migrate = SubCommand::with_name("migrate")
+        .about("Rewrites collections, their journals and histories saved by older versions in \
+                the current format")
+        .arg(Arg::with_name("collections")
+            .value_name("COLLECTION")
+            .multiple(true)
+            .help("The collections to migrate, instead of the one in the config file"));
+    let dict = SubCommand::with_name("dict")
+        .about("Manages the local dictionary")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("import")
+            .about("Adds the words of dictionary dumps to a dictionary index")
+            .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["krdict", "kengdic"])
+                .help("The kind of dump, instead of guessing it from the extension \
+                       (.xml for krdict, .tsv or .txt for kengdic)"))
+            .arg(Arg::with_name("index")
+                .long("index")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("The index to import into, instead of the dictionary file in the \
+                       config file"))
+            .arg(Arg::with_name("dumps").value_name("DUMP").multiple(true).required(true))
+            .after_help("Dumps can be the XML export of the Basic Korean Dictionary \
+                         (https://krdict.korean.go.kr)\nor the kengdic TSV file."));
+    let review = SubCommand::with_name("review")
+        .about("Reviews the meanings that are due today")
+        .arg(Arg::with_name("typed")
+            .long("typed")
+            .help("Asks for the term to be typed in, and grades the answer"))
+        .arg(Arg::with_name("new")
+            .long("new")
+            .takes_value(true)
+            .value_name("N")
+            .validator(validate_count)
+            .help("The most meanings to review for the first time today (default: 20)"));
+
+    App::new("memori")
+        .version(crate_version!())
+        .about("Keeps a collection of Korean vocabulary from plain text notes")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(Arg::with_name("config")
+            .long("config")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Reads the settings from this file instead of the default one"))
+        .subcommand(with_files(integrate, true, true))
+        .subcommand(with_files(resolve, true, true))
+        .subcommand(with_files(lookup, true, false))
+        .subcommand(with_files(rlookup, true, false))
+        .subcommand(with_files(remove, true, true))
+        .subcommand(with_files(rename, true, true))
+        .subcommand(with_files(merge, true, true))
+        .subcommand(with_files(unimport, true, true))
+        .subcommand(with_files(history, false, true))
+        .subcommand(with_files(revisit, true, true))
+        .subcommand(with_files(log, true, false))
+        .subcommand(with_files(undo, true, true))
+        .subcommand(check)
+        .subcommand(with_files(migrate, false, true))
+        .subcommand(dict)
+        .subcommand(with_files(review, true, false))
+        .after_help(CONFIG_HELP)
+}
+
+/// Loads the config file given with --config, or else the default one if
+/// there is one.
+fn load_config(path: Option<&str>) -> Result<Config, CliError> {
+    let (path, given) = match path {
+        Some(path) => (PathBuf::from(path), true),
+        None => {
+            match Config::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
             }
-            let colpath = &args[1];
-            let hispath = &args[2];
+        }
+    };
+    match Config::load(&path) {
+        Ok(Some(config)) => Ok(config),
+        Ok(None) if !given => Ok(Config::default()),
+        Ok(None) => {
+            let err = io::Error::new(io::ErrorKind::NotFound, "the file does not exist");
+            Err(CliError::Config(path.display().to_string(), ConfigError::Read(err)))
+        }
+        Err(err) => Err(CliError::Config(path.display().to_string(), err)),
+    }
+}
 
-            let source_paths = &args[3..];
+/// The files given to a command, or else those of its profile in the config
+/// file.
+struct Files<'a> {
+    args: &'a ArgMatches<'static>,
+    profile: Profile,
+}
 
-            let mut collection = load_collection(colpath)?;
-            let mut history = load_history(hispath)?;
+impl<'a> Files<'a> {
+    fn new(config: &Config, args: &'a ArgMatches<'static>) -> Result<Files<'a>, CliError> {
+        match config.profile(args.value_of("profile")) {
+            Some(profile) => {
+                Ok(Files {
+                    args: args,
+                    profile: profile,
+                })
+            }
+            None => {
+                let msg = format!("No profile '{}' in the config file",
+                                  args.value_of("profile").unwrap_or(""));
+                Err(CliError::Invalid(msg))
+            }
+        }
+    }
 
-            for source_path in source_paths {
-                let source = match Source::load(source_path) {
-                    Ok(s) => s,
-                    Err(err) => {
-                        println!("Could not read source at {}: {:?}", source_path, err);
-                        return Err(5);
-                    }
+    fn collection(&self) -> Result<String, CliError> {
+        self.path("collection", &self.profile.collection)
+    }
+
+    fn history(&self) -> Result<String, CliError> {
+        self.path("history", &self.profile.history)
+    }
+
+    fn path(&self, name: &str, configured: &Option<String>) -> Result<String, CliError> {
+        match (self.args.value_of(name), configured.as_ref().map(|p| p.as_str())) {
+            (Some(path), _) |
+            (None, Some(path)) => Ok(path.to_string()),
+            (None, None) => {
+                Err(CliError::Invalid(format!("No {} file given: use --{} or set '{}' in the \
+                                               config file",
+                                              name,
+                                              name,
+                                              name)))
+            }
+        }
+    }
+}
+
+/// Rejects sources given in the old 'integrate <collection> <history>
+/// <source>...' form, which would otherwise be read as sources. Sources are
+/// never JSON, so any JSON file is taken to be a collection or history.
+fn check_sources(sources: &[String]) -> Result<(), CliError> {
+    if let Some(path) = sources.iter().find(|path| path.ends_with(".json")) {
+        let msg = format!("'{}' looks like a collection or a history, which are no longer given \
+                           as arguments: use --collection and --history, or set them in the \
+                           config file",
+                          path);
+        return Err(CliError::Invalid(msg));
+    }
+    Ok(())
+}
+
+/// Returns the values of an argument that takes several.
+fn values_of(args: &ArgMatches, name: &str) -> Vec<String> {
+    args.values_of(name).map_or(Vec::new(), |values| values.map(|v| v.to_string()).collect())
+}
+
+fn run() -> Result<(), CliError> {
+    let matches = app().get_matches();
+    let config = load_config(matches.value_of("config"))?;
+    let (cmd, args) = matches.subcommand();
+    // A subcommand is required by the app
+    let args = args.expect("INVARIANT!");
+    let files = Files::new(&config, args)?;
+    match cmd {
+        "integrate" => {
+            let sources = values_of(args, "sources");
+            check_sources(&sources)?;
+            let options = IntegrateOptions {
+                policy: ConflictPolicy::from_str(args.value_of("on-conflict").unwrap_or("prompt"))
+                    .expect("INVARIANT!"),
+                dry_run: args.is_present("dry-run"),
+                json: args.is_present("json"),
+                decisions: args.value_of("decisions").map(|path| path.to_string()),
+            };
+            let mut prompter = conflict_prompter(args.value_of("decisions"), &config)?;
+            integrate(&files.collection()?,
+                      &files.history()?,
+                      &sources,
+                      &options,
+                      &mut *prompter)?;
+        }
+        "resolve" => {
+            let mut prompter = conflict_prompter(args.value_of("decisions"), &config)?;
+            resolve(&files.collection()?, &files.history()?, &mut *prompter)?;
+        }
+        "rlookup" => {
+            let query = values_of(args, "text").join(" ");
+            let collection = load_collection(&files.collection()?)?;
+            let matches = MeaningIndex::new(&collection).search(&query);
+            if matches.is_empty() {
+                println!("No terms found");
+            }
+            for found in matches.iter().take(MAX_MATCHES) {
+                let ref meaning = collection.meanings(&found.term)[found.index];
+                let title = collection.title(meaning.source).map_or("?", |t| t.as_str());
+                println!("{} | {} ['{}']", found.term, meaning.text, title);
+            }
+            if matches.len() > MAX_MATCHES {
+                println!("... and {} more", matches.len() - MAX_MATCHES);
+            }
+        }
+        "remove" => {
+            let (colpath, hispath) = (files.collection()?, files.history()?);
+            let term = args.value_of("term").expect("INVARIANT!");
+            let index = args.value_of("index")
+                .and_then(|index| index.parse::<usize>().ok())
+                .expect("INVARIANT!");
+            let mut collection = load_collection(&colpath)?;
+            let mut history = load_history(&hispath)?;
+            let removed = match collection.remove_meaning(term, index) {
+                Ok(removed) => removed,
+                Err(msg) => {
+                    return Err(CliError::Invalid(msg));
+                }
+            };
+            println!("Removed ({} | {})", term, removed.text);
+            if args.is_present("forget") {
+                // Let the next integrate offer it again
+                history.remove(term, &removed.text);
+            }
+            save_collection(&collection, &colpath)?;
+            save_history(&history, &hispath)?;
+        }
+        "rename" | "merge" => {
+            let (colpath, hispath) = (files.collection()?, files.history()?);
+            let term = args.value_of("term").expect("INVARIANT!");
+            let new_term = args.value_of("new-term").expect("INVARIANT!");
+            let mut collection = load_collection(&colpath)?;
+            let mut history = load_history(&hispath)?;
+            let res = if cmd == "rename" {
+                collection.rename_term(term, new_term.to_string())
+                    .map(|_| println!("Renamed '{}' to '{}'", term, new_term))
+            } else {
+                collection.merge_terms(term, new_term)
+                    .map(|n| println!("Merged {} meaning(s) of '{}' into '{}'", n, term, new_term))
+            };
+            if let Err(msg) = res {
+                return Err(CliError::Invalid(msg));
+            }
+            history.copy_term(term, new_term);
+            save_collection(&collection, &colpath)?;
+            save_history(&history, &hispath)?;
+        }
+        "unimport" => {
+            let (colpath, hispath) = (files.collection()?, files.history()?);
+            let title = args.value_of("title").expect("INVARIANT!");
+            let mut collection = load_collection(&colpath)?;
+            let mut history = load_history(&hispath)?;
+            let unimport = match collection.unimport(title) {
+                Ok(unimport) => unimport,
+                Err(msg) => {
+                    return Err(CliError::Invalid(msg));
+                }
+            };
+            for &(ref term, ref text) in &unimport.undone {
+                history.remove(term, text);
+            }
+            history.remove_source(title);
+            for &(ref term, ref text) in &unimport.restored {
+                println!("Restored ({} | {})", term, text);
+            }
+            println!("Undid {} change(s) from '{}', {} of them by restoring an older meaning",
+                     unimport.undone.len(),
+                     title,
+                     unimport.restored.len());
+            save_collection(&collection, &colpath)?;
+            save_history(&history, &hispath)?;
+        }
+        "history" => {
+            let history = load_history(&files.history()?)?;
+            let filter = parse_filter(args);
+            let records = history.query(&filter);
+            for &(term, record) in &records {
+                print_record(term, record);
+            }
+            println!("{} note(s)", records.len());
+        }
+        "revisit" => {
+            let mut filter = parse_filter(args);
+            filter.outcome = None;
+            let mut prompter = TerminalPrompter::new(&config);
+            revisit(&files.collection()?, &files.history()?, &filter, &mut prompter)?;
+        }
+        "log" => {
+            let journal = load_journal(&Journal::path_for(&files.collection()?))?;
+            if journal.runs().is_empty() {
+                println!("No runs recorded");
+            }
+            for run in journal.runs() {
+                let state = if run.reverted {
+                    "reverted"
+                } else if run.complete {
+                    "complete"
+                } else {
+                    "stopped early"
                 };
-                for note in &source.contents {
-                    // If not note handled in history
-                    if history.contains(&note.term, &note.meaning.text) {
-                        println!("- Skipping ({} | {})", &note.term, &note.meaning.text);
-                        continue;
-                    }
-                    if !collection.contains(&note.term, &note.meaning.text) {
-                        println!("Adding ({} | {})!", &note.term, &note.meaning.text);
-                        history.insert(note.term.clone(), note.meaning.text.clone());
-                        collection.add_meaning(note.term.clone(), note.meaning.clone(), &source.title);
-                        continue;
-                    }
-                    open_in_dictionary(&note.term);
-                    match prompt_decision(&collection, note, &source.title) {
-                        Ok(Decision::Reject) => {
-                            println!("Rejected!");
-                            history.insert(note.term.clone(), note.meaning.text.clone());
-                        }
-                        Ok(Decision::Add(meaning)) => {
-                            println!("Adding new!");
-                            history.insert(note.term.clone(), meaning);
-                            collection.add_meaning(note.term.clone(),
-                                                   note.meaning.clone(),
-                                                   &source.title);
-                        }
-                        Ok(Decision::Update(index, meaning)) => {
-                            println!("Updating [{}] => {}", index, meaning);
-                            history.insert(note.term.clone(), note.meaning.text.clone());
-                            collection.replace_meaning(&note.term, index, 
-                                meaning, None, &source.title).expect("INVARIANT!");
-                        }
-                        Err(err) => {
-                            // Save and quit
-                            save_collection(&collection, colpath)?;
-                            save_history(&history, hispath)?;
-                            Err(err)?;
-                        }
+                println!("#{} {} {} ({}): {} term(s) changed, {} deferred, {} resolved",
+                         run.id,
+                         date::format_timestamp(run.started),
+                         run.command,
+                         state,
+                         run.changes.len(),
+                         run.deferred.len(),
+                         run.resolved.len());
+                for source in &run.sources {
+                    println!("    {}", source);
+                }
+            }
+        }
+        "undo" => {
+            let (colpath, hispath) = (files.collection()?, files.history()?);
+            let jourpath = Journal::path_for(&colpath);
+            let mut journal = load_journal(&jourpath)?;
+            let id = match args.value_of("run") {
+                Some(id) => id.trim_left_matches('#').parse::<u32>().expect("INVARIANT!"),
+                None => {
+                    match journal.last_run() {
+                        Some(id) => id,
+                        None => return Err(CliError::Invalid("Nothing to undo".to_string())),
                     }
                 }
+            };
+            let mut collection = load_collection(&colpath)?;
+            let mut history = load_history(&hispath)?;
+            let skipped = match journal.run_mut(id) {
+                Some(run) if !run.reverted => {
+                    let skipped = journal::revert(run,
+                                                  &mut collection,
+                                                  &mut history,
+                                                  args.is_present("force"));
+                    run.reverted = true;
+                    println!("Reverted run #{} ({} term(s))",
+                             id,
+                             run.changes.len() - skipped.len());
+                    skipped
+                }
+                Some(_) => {
+                    let msg = format!("Run #{} has already been reverted", id);
+                    return Err(CliError::Invalid(msg));
+                }
+                None => {
+                    return Err(CliError::Invalid(format!("No run #{}", id)));
+                }
+            };
+            for term in &skipped {
+                println!("Kept '{}', it has been changed since", term);
             }
-
-            save_collection(&mut collection, colpath)?;
-            save_history(&history, hispath)?;
+            if !skipped.is_empty() {
+                println!("Use --force to revert those terms anyway");
+            }
+            save_collection(&collection, &colpath)?;
+            save_history(&history, &hispath)?;
+            write_journal(&journal, &jourpath)?;
+        }
+        "migrate" => {
+            let mut colpaths = values_of(args, "collections");
+            if colpaths.is_empty() {
+                colpaths.push(files.collection()?);
+            }
+            let hispath = files.history().ok();
+            migrate_files(&colpaths, hispath.as_ref().map(|p| p.as_str()))?;
         }
         "lookup" => {
-            if args.len() < 3 {
-                print_usage(1)?;
-            }
-            let colpath = &args[1];
-            let term = &args[2];
-            let collection = load_collection(colpath)?;
-            let ref meanings = collection.meanings(term);
-            if meanings.len() == 0 {
+            let term = args.value_of("term").expect("INVARIANT!");
+            let verbose = args.is_present("verbose");
+            let collection = load_collection(&files.collection()?)?;
+            let dictionary = load_dictionary(&config)?;
+            if !collection.meanings(term).is_empty() {
+                print_meanings(&collection, term, verbose);
+                if let Some(ref dictionary) = dictionary {
+                    print_definitions(dictionary, term);
+                }
+                return Ok(());
+            }
+            if let Some(ref dictionary) = dictionary {
+                print_definitions(dictionary, term);
+            }
+            if term.chars().any(jamo::is_consonant) {
+                let found = collection.search_initials(term);
+                if found.is_empty() {
+                    println!("No terms found with the initials '{}'", term);
+                }
+                for found_term in found.iter().take(MAX_MATCHES) {
+                    println!("{}", found_term);
+                    print_meanings(&collection, found_term, verbose);
+                }
+                if found.len() > MAX_MATCHES {
+                    println!("... and {} more", found.len() - MAX_MATCHES);
+                }
+                return Ok(());
+            }
+            let closest = TermIndex::new(&collection).closest(term);
+            if closest.is_empty() {
                 println!("No meanings found");
             } else {
-                for (i, meaning) in meanings.iter().enumerate() {
-                    println!("{}) {} ['{}']",
-                             i,
-                             meaning.text,
-                             collection.title(meaning.source).unwrap());
+                println!("No meanings found for '{}', did you mean:", term);
+                for &(distance, ref term) in closest.iter().take(MAX_FUZZY_MATCHES) {
+                    println!("{} ({} jamo off)", term, distance);
+                    print_meanings(&collection, term, verbose);
                 }
             }
         }
-        _ => {
-            println!("Unsupported command");
-            print_usage(1)?;
+        "dict" => {
+            // 'import' is the only subcommand, and one is required
+            let args = args.subcommand_matches("import").expect("INVARIANT!");
+            let index_path = match args.value_of("index") {
+                Some(path) => path.to_string(),
+                None => {
+                    match config.dictionary_file() {
+                        Some(path) => path,
+                        None => {
+                            let msg = "No dictionary index given: use --index or set 'file' in \
+                                       the [dictionary] section of the config file";
+                            return Err(CliError::Invalid(msg.to_string()));
+                        }
+                    }
+                }
+            };
+            let format = args.value_of("format")
+                .map(|format| DumpFormat::from_str(format).expect("INVARIANT!"));
+            import_dictionary(&index_path, &values_of(args, "dumps"), format)?;
         }
+        "review" => {
+            let colpath = files.collection()?;
+            let mut collection = load_collection(&colpath)?;
+            let today = date::today();
+            let new_per_day = args.value_of("new")
+                .map_or(NEW_PER_DAY, |n| n.parse::<usize>().expect("INVARIANT!"));
+            let due = collection.due(today, new_per_day);
+            if due.is_empty() {
+                println!("Nothing to review today!");
+                return Ok(());
+            }
+            // Every grade is saved right away, so quitting loses nothing
+            let mut n_reviewed = 0;
+            for (i, &(ref term, index)) in due.iter().enumerate() {
+                println!("[{}/{}]", i + 1, due.len());
+                let answer = if args.is_present("typed") {
+                    review_typed(&collection, term, index)
+                } else {
+                    review_card(&collection, term, index)
+                };
+                match answer {
+                    Ok(Some(grade)) => {
+                        collection.review(term, index, grade, today).expect("INVARIANT!");
+                        write_collection(&collection, &colpath, n_reviewed == 0)?;
+                        n_reviewed += 1;
+                    }
+                    Ok(None) => break,
+                    Err(err) => return Err(CliError::Review(err)),
+                }
+            }
+            println!("Reviewed {} of {} due card(s)", n_reviewed, due.len());
+        }
+        "check" => {
+            let mut n_invalid = 0;
+            for source_path in &values_of(args, "sources") {
+                match Source::load_lenient(source_path) {
+                    Ok((_, invalid_notes)) => {
+                        for invalid in &invalid_notes {
+                            print_invalid_note(invalid);
+                        }
+                        n_invalid += invalid_notes.len();
+                    }
+                    Err(err) => {
+                        return Err(CliError::Source(source_path.clone(), SourceLoadError::Io(err)));
+                    }
+                }
+            }
+            if n_invalid > 0 {
+                return Err(CliError::InvalidLines(n_invalid));
+            }
+            println!("All sources are valid");
+        }
+        _ => unreachable!(),
     }
     Ok(())
 }
 
 fn main() {
-    if let Err(errno) = run() {
-        process::exit(errno);
+    if let Err(err) = run() {
+        println!("{}", err);
+        process::exit(err.exit_code());
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use self::super::RE_VOCAB;
-
-    fn test_re(line: &str, e_korean: &str, e_meaning: &str, e_hanja: Option<&str>) {
-        if let Some(caps) = RE_VOCAB.captures(line) {
-            let mut korean = caps.get(1).unwrap().as_str().trim();
-            if korean.ends_with(":") {
-                korean = &korean[..korean.len() - 1];
-            }
-            let hanja = caps.get(2).unwrap().as_str().trim();
-            let hanja = if hanja != "" { Some(hanja) } else { None };
-            let meaning = caps.get(3).unwrap().as_str().trim();
-            assert_eq!(e_korean, korean);
-            assert_eq!(e_meaning, meaning);
-            assert_eq!(e_hanja, hanja);
-            //println!("{} | {} | {}", korean, hanja, meaning);
-        } else {
-            panic!("Could not read line: '{}'", line);
-        }
-    }
+    use std::io;
+    use self::super::{check_sources, CliError};
+    use memori::Error as FileError;
+    use memori::MigrateError;
+    use memori::config::ConfigError;
+    use memori::dictionary::ImportError;
+    use memori::prompt::PromptError;
+    use memori::session::SessionError;
+    use memori::source::SourceLoadError;
 
-    #[test]
-    fn vocab_re_1() {
-        let l1 = "적 tidspunkt (situation, oplevelse)";
-        test_re(l1, "적", "tidspunkt (situation, oplevelse)", None);
+    fn io_error() -> io::Error {
+        io::Error::new(io::ErrorKind::Other, "test")
     }
 
-    #[test]
-    fn vocab_re_2() {
-        let l2 = "AV~(으)ㄴ/는/(으)ㄹ 데 sted";
-        test_re(l2, "AV~(으)ㄴ/는/(으)ㄹ 데", "sted", None);
+    fn path() -> String {
+        "test.json".to_string()
     }
 
     #[test]
-    fn vocab_re_3() {
-        let l3 = "~복: 服 ~tøj";
-        test_re(l3, "~복", "~tøj", Some("服"));
+    fn exit_codes() {
+        let cases = vec![
+            (1, CliError::Invalid("test".to_string())),
+            (2, CliError::Collection(path(), FileError::Open(io_error()))),
+            (3, CliError::Collection(path(), FileError::Read(io_error()))),
+            (4, CliError::Collection(path(), FileError::Parse(MigrateError::NotAnObject))),
+            (4, CliError::Migrate(path(), MigrateError::UnsupportedVersion(1000))),
+            (5, CliError::Source(path(), SourceLoadError::Io(io_error()))),
+            (5, CliError::InvalidLines(2)),
+            (6, CliError::Collection(path(), FileError::Write(io_error()))),
+            (6, CliError::History(path(), FileError::Write(io_error()))),
+            (6, CliError::Journal(path(), FileError::Write(io_error()))),
+            (6, CliError::Dictionary(path(), FileError::Write(io_error()))),
+            (6, CliError::Backup(path(), path(), io_error())),
+            (7, CliError::Decide("학생".to_string(), "pupil".to_string(), io_error().into())),
+            (7, CliError::Review(io_error())),
+            (8, CliError::History(path(), FileError::Open(io_error()))),
+            (8, CliError::History(path(), FileError::Read(io_error()))),
+            (9, CliError::History(path(), FileError::Parse(MigrateError::NotAnObject))),
+            (10, CliError::Journal(path(), FileError::Read(io_error()))),
+            (10, CliError::Decisions(path(), "test".to_string())),
+            (10, CliError::Dictionary(path(), FileError::Open(io_error()))),
+            (10, CliError::Import(path(), ImportError::Io(io_error()))),
+            (11, CliError::Config(path(), ConfigError::Read(io_error()))),
+            (130, CliError::Interrupted),
+        ];
+        for (code, err) in cases {
+            assert_eq!(code, err.exit_code(), "{:?}", err);
+        }
     }
 
     #[test]
-    fn vocab_re_4() {
-        let l4 = "A~(으)ㄴ가요 blød interrogativ";
-        test_re(l4, "A~(으)ㄴ가요", "blød interrogativ", None);
+    fn session_errors() {
+        let history = SessionError::History(path(), FileError::Parse(MigrateError::NotAnObject));
+        assert_eq!(9, CliError::from(history).exit_code());
+        let decide = SessionError::Decide("학생".to_string(),
+                                          "pupil".to_string(),
+                                          PromptError::Io(io_error()));
+        assert_eq!(7, CliError::from(decide).exit_code());
+        assert_eq!(130, CliError::from(SessionError::Interrupted).exit_code());
     }
 
     #[test]
-    fn vocab_re_5() {
-        let l5 = "가상 현실 virtual reality";
-        test_re(l5, "가상 현실", "virtual reality", None);
+    fn old_integrate_form() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert!(check_sources(&args(&["col.json", "his.json", "lesson.txt"])).is_err());
+        assert!(check_sources(&args(&["lesson 1.txt", "lesson 2.txt", "lesson 3.txt"])).is_ok());
+        assert!(check_sources(&args(&["col.json", "his.json"])).is_err());
+        assert!(check_sources(&args(&["lesson.txt", "notes.json"])).is_err());
     }
 }
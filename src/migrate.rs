@@ -0,0 +1,282 @@
+use serde_json::{self, Value};
+use std::error::Error;
+use std::fmt;
+
+/// The collection schema written by this version of memori.
+pub const COLLECTION_VERSION: u32 = 2;
+/// The history schema written by this version of memori.
+pub const HISTORY_VERSION: u32 = 1;
+/// The journal schema written by this version of memori.
+pub const JOURNAL_VERSION: u32 = 1;
+/// The dictionary index schema written by this version of memori.
+pub const DICTIONARY_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum MigrateError {
+    Json(serde_json::Error),
+    NotAnObject,
+    /// The version field is not a schema version. Holds the field as JSON.
+    InvalidVersion(String),
+    /// The file was written by a newer version of memori.
+    UnsupportedVersion(u32),
+}
+
+impl From<serde_json::Error> for MigrateError {
+    fn from(err: serde_json::Error) -> MigrateError {
+        MigrateError::Json(err)
+    }
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MigrateError::Json(ref err) => write!(f, "{}", err),
+            MigrateError::NotAnObject => write!(f, "the file is not a JSON object"),
+            MigrateError::InvalidVersion(ref version) => {
+                write!(f, "{} is not a valid schema version", version)
+            }
+            MigrateError::UnsupportedVersion(version) => {
+                write!(f,
+                       "schema version {} is newer than this version of memori supports",
+                       version)
+            }
+        }
+    }
+}
+
+impl Error for MigrateError {
+    fn description(&self) -> &str {
+        match *self {
+            MigrateError::Json(ref err) => err.description(),
+            MigrateError::NotAnObject => "the file is not a JSON object",
+            MigrateError::InvalidVersion(_) => "invalid schema version",
+            MigrateError::UnsupportedVersion(_) => "unsupported schema version",
+        }
+    }
+}
+
+type Migration = fn(Value) -> Result<Value, MigrateError>;
+
+/// MIGRATIONS[n] upgrades a collection from version n to version n + 1.
+const MIGRATIONS: [Migration; COLLECTION_VERSION as usize] = [v0_to_v1, v1_to_v2];
+
+/// HISTORY_MIGRATIONS[n] upgrades a history from version n to version n + 1.
+const HISTORY_MIGRATIONS: [Migration; HISTORY_VERSION as usize] = [history_v0_to_v1];
+
+/// JOURNAL_MIGRATIONS[n] upgrades a journal from version n to version n + 1.
+const JOURNAL_MIGRATIONS: [Migration; JOURNAL_VERSION as usize] = [journal_v0_to_v1];
+
+/// DICTIONARY_MIGRATIONS[n] upgrades a dictionary index from version n to
+/// version n + 1.
+const DICTIONARY_MIGRATIONS: [Migration; DICTIONARY_VERSION as usize] = [dictionary_v0_to_v1];
+
+/// Runs the migrations from the version of the value on.
+fn migrate(mut value: Value, migrations: &[Migration]) -> Result<(Value, u32), MigrateError> {
+    let version = match value.get("version") {
+        Some(version) => {
+            match version.as_u64() {
+                Some(n) if n <= u32::max_value() as u64 => n as u32,
+                _ => return Err(MigrateError::InvalidVersion(version.to_string())),
+            }
+        }
+        None => 0,
+    };
+    if version as usize > migrations.len() {
+        return Err(MigrateError::UnsupportedVersion(version));
+    }
+    for migration in &migrations[version as usize..] {
+        value = migration(value)?;
+    }
+    Ok((value, version))
+}
+
+/// Upgrades a serialized collection to the current schema version.
+/// Returns the upgraded collection and the version it had.
+pub fn migrate_collection(value: Value) -> Result<(Value, u32), MigrateError> {
+    migrate(value, &MIGRATIONS)
+}
+
+/// Upgrades a serialized history to the current schema version.
+/// Returns the upgraded history and the version it had.
+pub fn migrate_history(value: Value) -> Result<(Value, u32), MigrateError> {
+    migrate(value, &HISTORY_MIGRATIONS)
+}
+
+/// Upgrades a serialized journal to the current schema version.
+/// Returns the upgraded journal and the version it had.
+pub fn migrate_journal(value: Value) -> Result<(Value, u32), MigrateError> {
+    migrate(value, &JOURNAL_MIGRATIONS)
+}
+
+/// Upgrades a serialized dictionary index to the current schema version.
+/// Returns the upgraded index and the version it had.
+pub fn migrate_dictionary(value: Value) -> Result<(Value, u32), MigrateError> {
+    migrate(value, &DICTIONARY_MIGRATIONS)
+}
+
+/// Version 0 had no version field, and serialized the empty list of meanings
+/// returned for unknown terms.
+fn v0_to_v1(mut value: Value) -> Result<Value, MigrateError> {
+    value.as_object_mut().ok_or(MigrateError::NotAnObject)?.remove("empty");
+    Ok(value)
+}
+
+/// Version 2 keeps a list of the changes made to each meaning. Older
+/// meanings only knew their latest source, which becomes an undated 'Add'.
+fn v1_to_v2(mut value: Value) -> Result<Value, MigrateError> {
+    fn add_provenance(meaning: &mut Value) -> Result<(), MigrateError> {
+        let meaning = meaning.as_object_mut().ok_or(MigrateError::NotAnObject)?;
+        let source = meaning.get("source").cloned().unwrap_or(Value::Null);
+        let provenance = json!([{
+            "source": source,
+            "lineno": null,
+            "action": "Add",
+            "timestamp": null
+        }]);
+        meaning.insert("provenance".to_string(), provenance);
+        if let Some(previous) = meaning.get_mut("previous") {
+            if !previous.is_null() {
+                add_provenance(previous)?;
+            }
+        }
+        Ok(())
+    }
+
+    let contents = value.get_mut("contents")
+        .and_then(|c| c.as_object_mut())
+        .ok_or(MigrateError::NotAnObject)?;
+    for (_, meanings) in contents.iter_mut() {
+        for meaning in meanings.as_array_mut().ok_or(MigrateError::NotAnObject)? {
+            add_provenance(meaning)?;
+        }
+    }
+    Ok(value)
+}
+
+/// Version 1 of the history records how and when each note was handled.
+/// Older histories only had the handled meanings of each term, which become
+/// records with nothing else known.
+fn history_v0_to_v1(mut value: Value) -> Result<Value, MigrateError> {
+    let handled = value.get_mut("handled")
+        .and_then(|h| h.as_object_mut())
+        .ok_or(MigrateError::NotAnObject)?;
+    for (_, meanings) in handled.iter_mut() {
+        let records = meanings.as_array()
+            .ok_or(MigrateError::NotAnObject)?
+            .iter()
+            .map(|meaning| {
+                json!({
+                    "meaning": meaning,
+                    "outcome": null,
+                    "timestamp": null,
+                    "source": null,
+                    "lineno": null,
+                    "replacement": null
+                })
+            })
+            .collect();
+        *meanings = Value::Array(records);
+    }
+    Ok(value)
+}
+
+/// Version 1 of the journal keeps the history records of each changed term
+/// from before and after the run. Older changes only listed the meanings the
+/// run marked as handled; they keep that list and get no records.
+fn journal_v0_to_v1(mut value: Value) -> Result<Value, MigrateError> {
+    let runs = value.get_mut("runs")
+        .and_then(|r| r.as_array_mut())
+        .ok_or(MigrateError::NotAnObject)?;
+    for run in runs {
+        let changes = run.get_mut("changes")
+            .and_then(|c| c.as_array_mut())
+            .ok_or(MigrateError::NotAnObject)?;
+        for change in changes {
+            let change = change.as_object_mut().ok_or(MigrateError::NotAnObject)?;
+            change.insert("records_before".to_string(), json!([]));
+            change.insert("records_after".to_string(), json!([]));
+        }
+    }
+    Ok(value)
+}
+
+/// Version 0 of the dictionary index had no version field, and is
+/// otherwise the same.
+fn dictionary_v0_to_v1(value: Value) -> Result<Value, MigrateError> {
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{migrate_collection, migrate_history, migrate_journal, MigrateError};
+    use serde_json::{self, Value};
+
+    #[test]
+    fn from_v0() {
+        let json = r#"{"contents":{},"titles":{},"titles_rev":{},"next_title_id":0,"empty":[]}"#;
+        let value: Value = serde_json::from_str(json).unwrap();
+        let (value, version) = migrate_collection(value).unwrap();
+        assert_eq!(0, version);
+        assert!(value.get("empty").is_none());
+    }
+
+    #[test]
+    fn from_v1() {
+        let json = r#"{"version":1,"contents":{"학생":[{"text":"elev","symbol":null,"source":3}]},
+                       "titles":{"3":"Lesson 3"},"titles_rev":{"Lesson 3":3},"next_title_id":4}"#;
+        let value: Value = serde_json::from_str(json).unwrap();
+        let (value, version) = migrate_collection(value).unwrap();
+        assert_eq!(1, version);
+        let ref provenance = value["contents"]["학생"][0]["provenance"];
+        assert_eq!(Some(3), provenance[0]["source"].as_u64());
+        assert_eq!(Some("Add"), provenance[0]["action"].as_str());
+    }
+
+    #[test]
+    fn too_new() {
+        let value: Value = serde_json::from_str(r#"{"version":1000}"#).unwrap();
+        match migrate_collection(value) {
+            Err(MigrateError::UnsupportedVersion(1000)) => {}
+            other => panic!("Expected an unsupported version, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_version() {
+        for json in &[r#"{"version":"2"}"#, r#"{"version":1.5}"#, r#"{"version":-1}"#] {
+            let value: Value = serde_json::from_str(json).unwrap();
+            match migrate_history(value) {
+                Err(MigrateError::InvalidVersion(_)) => {}
+                other => panic!("Expected an invalid version, got {:?}", other),
+            }
+        }
+        let value: Value = serde_json::from_str(r#"{"version":"2"}"#).unwrap();
+        let err = migrate_collection(value).unwrap_err();
+        assert_eq!("\"2\" is not a valid schema version", err.to_string());
+    }
+
+    #[test]
+    fn history_from_v0() {
+        let json = r#"{"handled":{"학생":["elev","student"]},"pending":[]}"#;
+        let value: Value = serde_json::from_str(json).unwrap();
+        let (value, version) = migrate_history(value).unwrap();
+        assert_eq!(0, version);
+        let ref records = value["handled"]["학생"];
+        assert_eq!(Some("elev"), records[0]["meaning"].as_str());
+        assert!(records[1]["outcome"].is_null());
+    }
+
+    #[test]
+    fn journal_from_v0() {
+        let json = r#"{"runs":[{"id":0,"command":"integrate","sources":["One"],"started":0,
+                       "complete":true,"reverted":false,"deferred":[],"resolved":[],
+                       "changes":[{"term":"학생","before":null,"after":null,
+                                   "handled":["elev"]}]}],"next_id":1}"#;
+        let value: Value = serde_json::from_str(json).unwrap();
+        let (value, version) = migrate_journal(value).unwrap();
+        assert_eq!(0, version);
+        let ref change = value["runs"][0]["changes"][0];
+        assert_eq!(Some(0), change["records_before"].as_array().map(|r| r.len()));
+        assert_eq!(Some("elev"), change["handled"][0].as_str());
+    }
+}
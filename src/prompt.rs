@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use collection::Collection;
+use dictionary::Dictionary;
+use integrate::Decision;
+use source::Note;
+
+#[derive(Debug)]
+pub enum PromptError {
+    /// The answer could not be read, or the input ended.
+    Io(io::Error),
+    /// A scripted decision is missing for a note, or doesn't fit it.
+    Script {
+        term: String,
+        meaning: String,
+        reason: &'static str,
+    },
+}
+
+impl From<io::Error> for PromptError {
+    fn from(err: io::Error) -> PromptError {
+        PromptError::Io(err)
+    }
+}
+
+impl fmt::Display for PromptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PromptError::Io(ref err) => write!(f, "could not read the answer: {}", err),
+            PromptError::Script { ref term, ref meaning, reason } => {
+                write!(f, "no decision for ({} | {}): {}", term, meaning, reason)
+            }
+        }
+    }
+}
+
+impl error::Error for PromptError {
+    fn description(&self) -> &str {
+        match *self {
+            PromptError::Io(_) => "could not read the answer",
+            PromptError::Script { reason, .. } => reason,
+        }
+    }
+}
+
+/// Makes the decisions on conflicting notes that the conflict policy leaves
+/// open.
+pub trait Prompter {
+    /// Decides what to do with a note whose term has other meanings.
+    /// `edited` is the index of the meaning that came from an earlier version
+    /// of the note's line, if any.
+    fn decide(&mut self,
+              collection: &Collection,
+              note: &Note,
+              source_title: &str,
+              edited: Option<usize>)
+              -> Result<Decision, PromptError>;
+
+    /// Whether a person is asked. Sessions save before asking a person, so
+    /// that they can quit with Ctrl-C while thinking it over.
+    fn is_interactive(&self) -> bool {
+        true
+    }
+}
+
+/// Asks for an answer on stdin until `convertor` accepts it. An initial
+/// answer given along with the command is tried first.
+pub fn prompt_answer<T, F: FnMut(&str) -> Option<T>>(initial: Option<&str>,
+                                                     mut convertor: F)
+                                                     -> Result<T, io::Error> {
+    if let Some(initial) = initial {
+        if let Some(res) = convertor(initial) {
+            return Ok(res);
+        }
+    }
+    let mut input = String::new();
+    print!("> ");
+    loop {
+        io::stdout().flush()?;
+        input.clear();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stdin was closed"));
+        }
+        if let Some(res) = convertor(input.trim()) {
+            return Ok(res);
+        } else {
+            print!("! ");
+        }
+    }
+}
+
+fn prompt_index(initial: &str, n_meanings: usize) -> Result<usize, io::Error> {
+    prompt_answer(if initial == "" { None } else { Some(initial) },
+                  |inp| if let Ok(index) = inp.parse::<usize>() {
+                      if index < n_meanings {
+                          Some(index)
+                      } else {
+                          println!("Index too big (>= {})", n_meanings);
+                          None
+                      }
+                  } else {
+                      None
+                  })
+}
+
+/// Prints the entries of a term in a dictionary, with their senses.
+pub fn print_definitions(dictionary: &Dictionary, term: &str) {
+    let entries = dictionary.lookup(term);
+    if entries.is_empty() {
+        return;
+    }
+    println!("Dictionary:");
+    for entry in entries {
+        println!("  {}", entry.heading(term));
+        for sense in &entry.senses {
+            println!("    - {}", sense);
+        }
+    }
+}
+
+/// Asks on the terminal, showing the definitions of the term from a local
+/// dictionary if there is one.
+#[derive(Debug, Clone, Default)]
+pub struct StdinPrompter {
+    pub dictionary: Option<Dictionary>,
+}
+
+impl Prompter for StdinPrompter {
+    fn decide(&mut self,
+              collection: &Collection,
+              note: &Note,
+              source_title: &str,
+              edited: Option<usize>)
+              -> Result<Decision, PromptError> {
+        println!("New meaning found: ( line {} of '{}' )", note.lineno, source_title);
+        println!("{} | {}", note.term, note.meaning.text);
+        if let Some(index) = edited {
+            println!("Line {} used to be meaning {}, it can be [e]dited in place",
+                     note.lineno,
+                     index);
+        }
+        println!("Existing meanings:");
+        let n_meanings = collection.meanings(&note.term).len();
+        for (i, meaning) in collection.meanings(&note.term).iter().enumerate() {
+            let title = collection.title(meaning.source).map_or("?", |t| t.as_str());
+            println!("{}) {} ['{}']", i, meaning.text, title);
+        }
+        if let Some(ref dictionary) = self.dictionary {
+            print_definitions(dictionary, &note.term);
+        }
+        if edited.is_some() {
+            println!("[a]dd [r]eplace [u]pdate [i]gnore [e]dit");
+        } else {
+            println!("[a]dd [r]eplace [u]pdate [i]gnore");
+        }
+        print!("> ");
+        loop {
+            io::stdout().flush()?;
+            let mut input = String::new();
+            if io::stdin().read_line(&mut input)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stdin was closed").into());
+            }
+            let answer = input.trim();
+            let (cmd, rem) = if let Some(index) = answer.find(" ") {
+                (&answer[..index], &answer[index + 1..])
+            } else {
+                (answer, "")
+            };
+            match cmd {
+                "a" => {
+                    return Ok(Decision::Add);
+                }
+                "r" => {
+                    println!("Please choose the index to replace ({} : {}):",
+                             0,
+                             n_meanings - 1);
+                    let index = prompt_index(rem, n_meanings)?;
+                    return Ok(Decision::Update(index, note.meaning.text.clone()));
+                }
+                "u" => {
+                    println!("Please choose the index to update ({} : {}):",
+                             0,
+                             n_meanings - 1);
+                    let (indextext, updatetext) = if let Some(index) = rem.find(" ") {
+                        (&rem[..index], Some(&rem[index..]))
+                    } else {
+                        (rem, None)
+                    };
+                    let index = prompt_index(indextext, n_meanings)?;
+                    let text = prompt_answer(updatetext, |inp| if inp != "" {
+                        Some(inp.to_string())
+                    } else {
+                        None
+                    })?;
+                    return Ok(Decision::Update(index, text));
+                }
+                "i" => {
+                    return Ok(Decision::Reject);
+                }
+                "e" if edited.is_some() => {
+                    return Ok(Decision::Edit(edited.expect("INVARIANT!")));
+                }
+                _ => {
+                    println!("Unrecognized command: '{}'", cmd);
+                }
+            }
+        }
+    }
+}
+
+/// Decides from a list of decisions for known notes, for tests and batch
+/// runs.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedPrompter {
+    decisions: HashMap<(String, String), Decision>,
+}
+
+impl ScriptedPrompter {
+    #[inline]
+    pub fn new() -> ScriptedPrompter {
+        ScriptedPrompter::default()
+    }
+
+    /// Sets the decision for the note with the given term and meaning. The
+    /// index of an Edit is ignored, since it is the edited meaning.
+    pub fn insert(&mut self, term: &str, meaning: &str, decision: Decision) {
+        self.decisions.insert((term.to_string(), meaning.to_string()), decision);
+    }
+
+    /// Reads decisions written one per line as tab-separated fields:
+    ///
+    /// ```text
+    /// <term>  <meaning>  add | ignore | defer | edit
+    /// <term>  <meaning>  replace  <index>
+    /// <term>  <meaning>  update  <index>  <new text>
+    /// ```
+    ///
+    /// Empty lines and lines starting with '#' are skipped. Errors name the
+    /// line they are on.
+    pub fn parse(text: &str) -> Result<ScriptedPrompter, String> {
+        let mut prompter = ScriptedPrompter::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split('\t').map(|f| f.trim()).collect::<Vec<_>>();
+            if fields.len() < 3 {
+                return Err(format!("line {}: expected <term>, <meaning> and <decision> \
+                                    separated by tabs",
+                                   i + 1));
+            }
+            let index = || {
+                fields.get(3)
+                    .and_then(|f| f.parse::<usize>().ok())
+                    .ok_or(format!("line {}: expected the index of a meaning", i + 1))
+            };
+            let decision = match fields[2] {
+                "add" => Decision::Add,
+                "ignore" => Decision::Reject,
+                "defer" => Decision::Defer,
+                "edit" => Decision::Edit(0),
+                "replace" => Decision::Update(index()?, fields[1].to_string()),
+                "update" => {
+                    match fields.get(4) {
+                        Some(text) if !text.is_empty() => Decision::Update(index()?, text.to_string()),
+                        _ => return Err(format!("line {}: expected the new text", i + 1)),
+                    }
+                }
+                other => return Err(format!("line {}: unknown decision '{}'", i + 1, other)),
+            };
+            prompter.insert(fields[0], fields[1], decision);
+        }
+        Ok(prompter)
+    }
+}
+
+impl Prompter for ScriptedPrompter {
+    fn decide(&mut self,
+              collection: &Collection,
+              note: &Note,
+              _source_title: &str,
+              edited: Option<usize>)
+              -> Result<Decision, PromptError> {
+        let error = |reason| {
+            Err(PromptError::Script {
+                term: note.term.clone(),
+                meaning: note.meaning.text.clone(),
+                reason: reason,
+            })
+        };
+        let key = (note.term.clone(), note.meaning.text.clone());
+        match self.decisions.get(&key) {
+            None => error("it is not in the script"),
+            Some(&Decision::Edit(_)) => {
+                match edited {
+                    Some(index) => Ok(Decision::Edit(index)),
+                    None => error("its line wasn't edited"),
+                }
+            }
+            Some(&Decision::Update(index, _)) if index >= collection.meanings(&note.term).len() => {
+                error("the index is too big")
+            }
+            Some(decision) => Ok(decision.clone()),
+        }
+    }
+
+    #[inline]
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{ScriptedPrompter, Prompter};
+    use collection::Collection;
+    use integrate::Decision;
+    use testing::note;
+
+    #[test]
+    fn parse() {
+        let script = "# Lesson 3\n\
+                      학생\tpupil\tupdate\t0\tstudent, pupil\n\
+                      \n\
+                      사과\tapology\tadd\n\
+                      학교\tskolen\tedit\n";
+        let mut prompter = ScriptedPrompter::parse(script).unwrap();
+        let mut col = Collection::new();
+        col.add_meaning("학생".to_string(), note("학생", "student").meaning, "test", None);
+        assert_eq!(Decision::Update(0, "student, pupil".to_string()),
+                   prompter.decide(&col, &note("학생", "pupil"), "test", None).unwrap());
+        assert_eq!(Decision::Add,
+                   prompter.decide(&col, &note("사과", "apology"), "test", None).unwrap());
+        assert_eq!(Decision::Edit(2),
+                   prompter.decide(&col, &note("학교", "skolen"), "test", Some(2)).unwrap());
+        assert!(prompter.decide(&col, &note("학교", "skolen"), "test", None).is_err());
+        assert!(prompter.decide(&col, &note("학생", "elev"), "test", None).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(ScriptedPrompter::parse("학생\tpupil").is_err());
+        assert!(ScriptedPrompter::parse("학생\tpupil\treplace").is_err());
+        assert!(ScriptedPrompter::parse("학생\tpupil\tupdate\t0").is_err());
+        assert!(ScriptedPrompter::parse("학생\tpupil\tmaybe").is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use serde::Serialize;
+use serde_json;
+use error::Error;
+
+/// How many earlier versions of a file are kept when saving with a backup.
+pub const BACKUPS: usize = 3;
+
+fn backup_path(path: &str, n: usize) -> String {
+    format!("{}.bak.{}", path, n)
+}
+
+/// Moves '<path>.bak.1' to '<path>.bak.2' and so on, and the current file to
+/// '<path>.bak.1', dropping the oldest backup.
+fn rotate_backups(path: &str) -> io::Result<()> {
+    for n in (1..BACKUPS).rev() {
+        let older = backup_path(path, n);
+        if Path::new(&older).exists() {
+            fs::rename(&older, backup_path(path, n + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+/// Writes the contents to a temporary file next to the target and renames it
+/// over the target once everything is on disk, so that the target is never
+/// left half-written. If `backup` is set, the previous version is kept too.
+pub fn write_atomic(path: &str, contents: &[u8], backup: bool) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    if backup && Path::new(path).exists() {
+        rotate_backups(path)?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Reads a file, or returns None if it doesn't exist yet.
+pub fn read(path: &str) -> Result<Option<String>, Error> {
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+    let mut file = File::open(path).map_err(Error::Open)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).map_err(Error::Read)?;
+    Ok(Some(contents))
+}
+
+/// Writes a value as JSON with `write_atomic`.
+pub fn write_json<T: Serialize>(value: &T, path: &str, backup: bool) -> Result<(), Error> {
+    let serialized = serde_json::to_string(value).expect("INVARIANT!");
+    write_atomic(path, serialized.as_bytes(), backup).map_err(Error::Write)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use self::super::{backup_path, read, rotate_backups, write_atomic, BACKUPS};
+    use testing::{temp_dir, temp_path};
+
+    /// Returns a path in a fresh temporary directory.
+    fn file_path(name: &str) -> String {
+        temp_path(&temp_dir(&format!("save-{}", name)), "file.json")
+    }
+
+    fn contents(path: &str) -> String {
+        read(path).unwrap().unwrap()
+    }
+
+    #[test]
+    fn atomic() {
+        let path = file_path("atomic");
+        write_atomic(&path, b"one", false).unwrap();
+        write_atomic(&path, b"two", false).unwrap();
+        assert_eq!("two", contents(&path));
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+        assert!(!Path::new(&backup_path(&path, 1)).exists());
+    }
+
+    #[test]
+    fn backups() {
+        let path = file_path("backups");
+        // The first write has nothing to back up
+        write_atomic(&path, b"1", true).unwrap();
+        assert!(!Path::new(&backup_path(&path, 1)).exists());
+        for n in 2..(BACKUPS + 4) {
+            write_atomic(&path, n.to_string().as_bytes(), true).unwrap();
+        }
+        let last = BACKUPS + 3;
+        assert_eq!(last.to_string(), contents(&path));
+        for n in 1..(BACKUPS + 1) {
+            assert_eq!((last - n).to_string(), contents(&backup_path(&path, n)));
+        }
+        assert!(!Path::new(&backup_path(&path, BACKUPS + 1)).exists());
+    }
+
+    #[test]
+    fn rotate() {
+        let path = file_path("rotate");
+        write_atomic(&path, b"current", false).unwrap();
+        write_atomic(&backup_path(&path, 2), b"older", false).unwrap();
+        rotate_backups(&path).unwrap();
+        assert_eq!("current", contents(&path));
+        assert_eq!("current", contents(&backup_path(&path, 1)));
+        assert_eq!("older", contents(&backup_path(&path, 3)));
+        assert!(!Path::new(&backup_path(&path, 2)).exists());
+    }
+}
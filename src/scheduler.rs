@@ -0,0 +1,157 @@
+/*
+Spaced repetition following the SM-2 algorithm:
+https://www.supermemo.com/english/ol/sm2.htm
+The four grades map to the SM-2 response qualities 0, 3, 4 and 5.
+As in SM-2, a forgotten meaning starts over without changing its ease factor.
+*/
+
+/// The lowest ease factor a meaning can get.
+pub const MIN_EASE: f32 = 1.3;
+/// The most meanings reviewed for the first time on a day, by default.
+pub const NEW_PER_DAY: usize = 20;
+
+/// How well a meaning was remembered during a review.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grade {
+    Again,
+    Hard,
+    Good,
+    Easy,
+}
+
+impl Grade {
+    /// Reads a grade from its number (1-4) or name.
+    pub fn from_str(text: &str) -> Option<Grade> {
+        use self::Grade::*;
+        match text.to_lowercase().as_str() {
+            "1" | "again" => Some(Again),
+            "2" | "hard" => Some(Hard),
+            "3" | "good" => Some(Good),
+            "4" | "easy" => Some(Easy),
+            _ => None,
+        }
+    }
+
+    fn quality(self) -> f32 {
+        use self::Grade::*;
+        match self {
+            Again => 0.0,
+            Hard => 3.0,
+            Good => 4.0,
+            Easy => 5.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReviewState {
+    pub ease: f32,
+    /// The number of days between the last review and the next.
+    pub interval: u32,
+    /// The day (counted from the Unix epoch) the meaning is next due.
+    pub due: u32,
+    /// Correct reviews in a row.
+    pub repetitions: u32,
+    /// How many times the meaning has been forgotten.
+    pub lapses: u32,
+    /// The day the meaning was first reviewed, if it has been since this
+    /// was recorded.
+    #[serde(default)]
+    pub first_review: Option<u32>,
+}
+
+impl Default for ReviewState {
+    fn default() -> ReviewState {
+        ReviewState {
+            ease: 2.5,
+            interval: 0,
+            due: 0,
+            repetitions: 0,
+            lapses: 0,
+            first_review: None,
+        }
+    }
+}
+
+impl ReviewState {
+    #[inline]
+    pub fn is_due(&self, today: u32) -> bool {
+        self.due <= today
+    }
+
+    /// Returns whether the meaning has never been reviewed.
+    #[inline]
+    pub fn is_new(&self) -> bool {
+        self.due == 0
+    }
+
+    /// Returns whether the state is further along than another: reviewed,
+    /// at a longer interval, or with more correct reviews in a row.
+    pub fn is_ahead_of(&self, other: &ReviewState) -> bool {
+        (!self.is_new(), self.interval, self.repetitions) >
+        (!other.is_new(), other.interval, other.repetitions)
+    }
+
+    /// Schedules the next review after being graded on the given day.
+    pub fn review(&mut self, grade: Grade, today: u32) {
+        if self.is_new() {
+            self.first_review = Some(today);
+        }
+        if grade == Grade::Again {
+            self.repetitions = 0;
+            self.lapses += 1;
+            self.interval = 1;
+        } else {
+            self.interval = match self.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (self.interval as f32 * self.ease).round() as u32,
+            };
+            self.repetitions += 1;
+            let q = grade.quality();
+            self.ease = (self.ease + 0.1 - (5.0 - q) * (0.08 + (5.0 - q) * 0.02)).max(MIN_EASE);
+        }
+        self.due = today + self.interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{ReviewState, Grade, MIN_EASE};
+
+    #[test]
+    fn intervals() {
+        let mut state = ReviewState::default();
+        state.review(Grade::Good, 100);
+        assert_eq!((1, 101), (state.interval, state.due));
+        state.review(Grade::Good, 101);
+        assert_eq!((6, 107), (state.interval, state.due));
+        state.review(Grade::Good, 107);
+        assert_eq!((15, 122), (state.interval, state.due));
+    }
+
+    #[test]
+    fn lapse() {
+        let mut state = ReviewState::default();
+        state.review(Grade::Good, 0);
+        state.review(Grade::Good, 1);
+        let ease = state.ease;
+        state.review(Grade::Again, 7);
+        assert_eq!(0, state.repetitions);
+        assert_eq!(1, state.lapses);
+        assert_eq!(8, state.due);
+        assert_eq!(ease, state.ease);
+        assert_eq!(Some(0), state.first_review);
+    }
+
+    #[test]
+    fn ease() {
+        let mut state = ReviewState::default();
+        state.review(Grade::Easy, 0);
+        assert!((state.ease - 2.6).abs() < 1e-5);
+        for _ in 0..10 {
+            state.review(Grade::Hard, 0);
+        }
+        assert_eq!(MIN_EASE, state.ease);
+    }
+}
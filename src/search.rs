@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+use bktree::BkTree;
+use collection::Collection;
+use jamo::to_jamo;
+
+/// Shortest query word that also matches longer words it is a prefix of.
+const MIN_PREFIX: usize = 3;
+
+/// Folds a word for matching: lowercases it, removes accents, and spells the
+/// Danish æ, ø and å as 'ae', 'oe' and 'aa' so they can be typed without a
+/// Danish keyboard. They are never folded into a plain 'a' or 'o', since
+/// they are letters of their own.
+pub fn fold(word: &str) -> String {
+    let mut folded = String::with_capacity(word.len());
+    for ch in word.chars().flat_map(|c| c.to_lowercase()) {
+        match ch {
+            'æ' => folded.push_str("ae"),
+            'ø' => folded.push_str("oe"),
+            'å' => folded.push_str("aa"),
+            'à' | 'á' | 'â' | 'ã' | 'ä' => folded.push('a'),
+            'è' | 'é' | 'ê' | 'ë' => folded.push('e'),
+            'ì' | 'í' | 'î' | 'ï' => folded.push('i'),
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' => folded.push('o'),
+            'ù' | 'ú' | 'û' | 'ü' => folded.push('u'),
+            'ý' | 'ÿ' => folded.push('y'),
+            'ç' => folded.push('c'),
+            'ñ' => folded.push('n'),
+            _ => folded.push(ch),
+        }
+    }
+    folded
+}
+
+/// Splits a text into folded words.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(fold)
+        .collect()
+}
+
+/// A meaning found by a search.
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub term: String,
+    /// The index of the meaning among the meanings of the term.
+    pub index: usize,
+    pub score: f32,
+}
+
+/// An index from the words of meanings to the meanings they are part of.
+pub struct MeaningIndex {
+    words: BTreeMap<String, Vec<(String, usize)>>,
+    /// The number of words in each meaning.
+    lengths: BTreeMap<(String, usize), usize>,
+}
+
+impl MeaningIndex {
+    pub fn new(collection: &Collection) -> MeaningIndex {
+        let mut words = BTreeMap::new();
+        let mut lengths = BTreeMap::new();
+        for (term, meanings) in collection.iter() {
+            for (index, meaning) in meanings.iter().enumerate() {
+                let tokens = tokenize(&meaning.text);
+                lengths.insert((term.clone(), index), tokens.len());
+                for token in tokens {
+                    words.entry(token).or_insert(Vec::new()).push((term.clone(), index));
+                }
+            }
+        }
+        MeaningIndex {
+            words: words,
+            lengths: lengths,
+        }
+    }
+
+    /// Finds the meanings containing the words of the query, best matches
+    /// first. Whole words count fully and prefixes count half.
+    pub fn search(&self, query: &str) -> Vec<Match> {
+        let query = tokenize(query);
+        let mut scores: BTreeMap<(String, usize), (f32, usize)> = BTreeMap::new();
+        for word in &query {
+            let mut weights: BTreeMap<(String, usize), f32> = BTreeMap::new();
+            for (indexed, meanings) in self.words.range(word.clone()..) {
+                let weight = if indexed == word {
+                    1.0
+                } else if word.chars().count() >= MIN_PREFIX && indexed.starts_with(word.as_str()) {
+                    0.5
+                } else {
+                    break;
+                };
+                for key in meanings {
+                    let best = weights.entry(key.clone()).or_insert(0.0);
+                    *best = best.max(weight);
+                }
+            }
+            for (key, weight) in weights {
+                let score = scores.entry(key).or_insert((0.0, 0));
+                score.0 += weight;
+                score.1 += 1;
+            }
+        }
+        let mut matches = scores.into_iter()
+            .map(|((term, index), (weight, n_matched))| {
+                // Prefer meanings that the query covers more of
+                let length = self.lengths[&(term.clone(), index)].max(1);
+                let coverage = n_matched.min(length) as f32 / length as f32;
+                Match {
+                    term: term,
+                    index: index,
+                    score: weight / query.len() as f32 + 0.25 * coverage,
+                }
+            })
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap()
+                .then_with(|| a.term.cmp(&b.term))
+                .then(a.index.cmp(&b.index))
+        });
+        matches
+    }
+}
+
+/// Returns how many jamo edits a fuzzy match of the query may be off by.
+fn fuzzy_tolerance(query: &str) -> usize {
+    (to_jamo(query).len() / 3).max(1).min(3)
+}
+
+/// An index of the terms of a collection by their jamo, for finding the
+/// terms close to a possibly misspelled one without comparing it to every
+/// term.
+pub struct TermIndex {
+    tree: BkTree,
+}
+
+impl TermIndex {
+    pub fn new(collection: &Collection) -> TermIndex {
+        let mut tree = BkTree::new();
+        for (term, _) in collection.iter() {
+            tree.insert(term.clone());
+        }
+        TermIndex { tree: tree }
+    }
+
+    /// Finds the terms closest to a possibly misspelled one, closest first.
+    pub fn closest(&self, query: &str) -> Vec<(usize, String)> {
+        self.tree
+            .find(query, fuzzy_tolerance(query))
+            .into_iter()
+            .map(|(distance, term)| (distance, term.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{fold, tokenize, TermIndex};
+    use collection::Collection;
+    use std::char;
+    use testing::collection;
+
+    #[test]
+    fn danish() {
+        assert_eq!("blaabaergroed", fold("Blåbærgrød"));
+        assert_eq!(fold("søen"), fold("soeen"));
+        assert!(fold("så") != fold("sa"));
+    }
+
+    #[test]
+    fn accents() {
+        assert_eq!("ide", fold("idé"));
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(vec!["tidspunkt", "situation", "oplevelse"],
+                   tokenize("tidspunkt (situation, oplevelse)"));
+        assert_eq!(vec!["toej"], tokenize("~tøj"));
+    }
+
+    fn small() -> Collection {
+        collection(&["괜찮다", "감사", "감사합니다", "학생", "학교", "괜히"])
+    }
+
+    #[test]
+    fn exact() {
+        let col = small();
+        assert_eq!(vec![(0, "학생".to_string())], TermIndex::new(&col).closest("학생"));
+    }
+
+    #[test]
+    fn closest() {
+        let index = TermIndex::new(&small());
+        assert_eq!(vec![(1, "괜찮다".to_string())], index.closest("괜찬다"));
+        assert_eq!(vec![(1, "학교".to_string())], index.closest("학겨"));
+    }
+
+    #[test]
+    fn many_terms() {
+        let syllables = |first: u32, n: u32| {
+            (first..first + n)
+                .map(|c| char::from_u32(c).expect("INVARIANT!"))
+                .collect::<Vec<_>>()
+        };
+        // 40 * 30 * 25 three-syllable words, from 가가나 on
+        let firsts = syllables(0xAC00, 40);
+        let seconds = syllables(0xAC00, 30);
+        let thirds = syllables(0xB098, 25);
+        let mut terms = Vec::new();
+        for a in &firsts {
+            for b in &seconds {
+                for c in &thirds {
+                    terms.push(format!("{}{}{}", a, b, c));
+                }
+            }
+        }
+        assert_eq!(30000, terms.len());
+        let col = collection(&terms.iter().map(|t| t.as_str()).collect::<Vec<_>>());
+        let found = TermIndex::new(&col).closest("가각나");
+        assert_eq!((0, "가각나".to_string()), found[0]);
+        assert_eq!((1, "가가나".to_string()), found[1]);
+        assert!(found.iter().all(|&(distance, _)| distance <= 3));
+    }
+}
@@ -0,0 +1,323 @@
+use std::error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::thread;
+use std::time::Instant;
+use collection::Collection;
+use history::History;
+use integrate::{self, Action, ConflictPolicy};
+use journal::{Journal, Transaction};
+use prompt::{Prompter, PromptError};
+use source::Note;
+use error::Error;
+use date;
+
+/// Unsaved changes after which a session is checkpointed.
+const CHECKPOINT_CHANGES: usize = 50;
+/// Seconds after which unsaved changes in a session are checkpointed.
+const CHECKPOINT_SECS: u64 = 60;
+
+static INTERRUPTED: AtomicBool = ATOMIC_BOOL_INIT;
+static PROMPTING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Tells the running session that it should stop, like when Ctrl-C is
+/// pressed. It saves and returns `SessionError::Interrupted` after the note
+/// it is on. Returns true if the session is waiting for the prompter with
+/// everything saved, so that the process can quit right away.
+pub fn interrupt() -> bool {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    PROMPTING.load(Ordering::SeqCst)
+}
+
+/// Why a session stopped.
+#[derive(Debug)]
+pub enum SessionError {
+    Collection(String, Error),
+    History(String, Error),
+    Journal(String, Error),
+    /// A conflicting note (term, meaning) that could not be decided.
+    Decide(String, String, PromptError),
+    /// Ctrl-C was pressed, and the progress was saved.
+    Interrupted,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SessionError::Collection(ref path, ref err) => {
+                write!(f, "Collection file ('{}'): {}", path, err)
+            }
+            SessionError::History(ref path, ref err) => {
+                write!(f, "History file ('{}'): {}", path, err)
+            }
+            SessionError::Journal(ref path, ref err) => {
+                write!(f, "Journal file ('{}'): {}", path, err)
+            }
+            SessionError::Decide(ref term, ref meaning, ref err) => {
+                write!(f, "Could not decide ({} | {}): {}", term, meaning, err)
+            }
+            SessionError::Interrupted => write!(f, "Interrupted, progress has been saved"),
+        }
+    }
+}
+
+impl error::Error for SessionError {
+    fn description(&self) -> &str {
+        match *self {
+            SessionError::Collection(..) => "could not load or save the collection",
+            SessionError::History(..) => "could not load or save the history",
+            SessionError::Journal(..) => "could not load or save the journal",
+            SessionError::Decide(..) => "could not decide a conflict",
+            SessionError::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// A collection and history being changed by a long-running command.
+/// Changes are checkpointed as they come in, before every prompt, and if the
+/// command panics.
+pub struct Session {
+    pub collection: Collection,
+    pub history: History,
+    /// The collection and history files, or None if the session never saves.
+    files: Option<(String, String)>,
+    backed_up: bool,
+    unsaved: usize,
+    last_save: Instant,
+    /// The journal and the run being recorded in it, if any.
+    journal: Option<(Journal, Transaction)>,
+    /// Whether the run got to the end.
+    complete: bool,
+}
+
+impl Session {
+    /// Starts a session that is never saved.
+    pub fn new(collection: Collection, history: History) -> Session {
+        Session {
+            collection: collection,
+            history: history,
+            files: None,
+            backed_up: false,
+            unsaved: 0,
+            last_save: Instant::now(),
+            journal: None,
+            complete: false,
+        }
+    }
+
+    /// Loads the collection and history. A dry run never saves them.
+    pub fn load(colpath: &str, hispath: &str, dry_run: bool) -> Result<Session, SessionError> {
+        let collection = Collection::load(colpath)
+            .map_err(|err| SessionError::Collection(colpath.to_string(), err))?;
+        let history = History::load(hispath)
+            .map_err(|err| SessionError::History(hispath.to_string(), err))?;
+        let mut session = Session::new(collection, history);
+        if !dry_run {
+            session.files = Some((colpath.to_string(), hispath.to_string()));
+        }
+        Ok(session)
+    }
+
+    /// Starts recording the changes of the session in the journal, so that
+    /// they can be undone.
+    pub fn begin(&mut self, command: &str, sources: &[String]) -> Result<(), SessionError> {
+        let jourpath = match self.files {
+            Some((ref colpath, _)) => Journal::path_for(colpath),
+            None => return Ok(()),
+        };
+        let mut journal = Journal::load(&jourpath)
+            .map_err(|err| SessionError::Journal(jourpath.clone(), err))?;
+        let transaction = journal.begin(command, sources, self.history.pending(), date::now());
+        self.journal = Some((journal, transaction));
+        Ok(())
+    }
+
+    /// Remembers a term as it is before the session changes it.
+    pub fn touch(&mut self, term: &str) {
+        if let Some((_, ref mut transaction)) = self.journal {
+            transaction.touch(&self.collection, &self.history, term);
+        }
+    }
+
+    #[inline]
+    pub fn changed(&mut self) {
+        self.unsaved += 1;
+    }
+
+    /// Saves both files, along with the journal. The versions from before the
+    /// session are backed up the first time.
+    pub fn save(&mut self) -> Result<(), SessionError> {
+        let (colpath, hispath) = match self.files {
+            Some((ref colpath, ref hispath)) => (colpath, hispath),
+            None => return Ok(()),
+        };
+        self.collection
+            .save(colpath, !self.backed_up)
+            .map_err(|err| SessionError::Collection(colpath.clone(), err))?;
+        self.history
+            .save(hispath, !self.backed_up)
+            .map_err(|err| SessionError::History(hispath.clone(), err))?;
+        if let Some((ref mut journal, ref transaction)) = self.journal {
+            journal.record(transaction.to_run(&self.collection, &self.history, self.complete));
+            let jourpath = Journal::path_for(colpath);
+            journal.save(&jourpath).map_err(|err| SessionError::Journal(jourpath.clone(), err))?;
+        }
+        self.backed_up = true;
+        self.unsaved = 0;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+
+    /// Marks the run as complete and saves.
+    pub fn finish(&mut self) -> Result<(), SessionError> {
+        self.complete = true;
+        self.save()
+    }
+
+    /// Saves if a lot has changed, or if changes have been unsaved for a while.
+    pub fn checkpoint(&mut self) -> Result<(), SessionError> {
+        if self.unsaved >= CHECKPOINT_CHANGES ||
+           (self.unsaved > 0 && self.last_save.elapsed().as_secs() >= CHECKPOINT_SECS) {
+            self.save()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Saves and returns an error if Ctrl-C has been pressed.
+    pub fn check_interrupted(&mut self) -> Result<(), SessionError> {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            self.save()?;
+            return Err(SessionError::Interrupted);
+        }
+        Ok(())
+    }
+
+    /// Decides a conflicting note with the policy, or else by asking the
+    /// prompter, and applies the decision. The progress so far is saved
+    /// before a person is asked, so that Ctrl-C can quit during the prompt.
+    pub fn resolve_conflict<P: Prompter + ?Sized>(&mut self,
+                                                  note: &Note,
+                                                  source_title: &str,
+                                                  edited: Option<usize>,
+                                                  policy: ConflictPolicy,
+                                                  prompter: &mut P)
+                                                  -> Result<Action, SessionError> {
+        let n_meanings = self.collection.meanings(&note.term).len();
+        let decision = match policy.decide(n_meanings, note, edited) {
+            Some(decision) => decision,
+            None => {
+                let interactive = prompter.is_interactive();
+                if interactive && self.unsaved > 0 {
+                    self.save()?;
+                }
+                PROMPTING.store(interactive, Ordering::SeqCst);
+                let res = prompter.decide(&self.collection, note, source_title, edited);
+                PROMPTING.store(false, Ordering::SeqCst);
+                match res {
+                    Ok(decision) => decision,
+                    Err(err) => {
+                        let (term, meaning) = (note.term.clone(), note.meaning.text.clone());
+                        return Err(SessionError::Decide(term, meaning, err));
+                    }
+                }
+            }
+        };
+        let action = integrate::apply_decision(&mut self.collection,
+                                               &mut self.history,
+                                               note,
+                                               source_title,
+                                               decision);
+        self.changed();
+        Ok(action)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if thread::panicking() && self.unsaved > 0 {
+            println!("Saving progress before crashing...");
+            let _ = self.save();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use self::super::Session;
+    use collection::Collection;
+    use history::History;
+    use integrate::{integrate_source, Action, ConflictPolicy, Decision};
+    use journal::Journal;
+    use prompt::ScriptedPrompter;
+    use save;
+    use testing::{source, temp_dir, temp_path};
+
+    #[test]
+    fn dry_run() {
+        let dir = temp_dir("session-dry-run");
+        let (colpath, hispath) = (temp_path(&dir, "col.json"), temp_path(&dir, "his.json"));
+
+        let mut session = Session::new(Collection::new(), History::new());
+        let one = source("One", &[("학생", "student")]);
+        integrate_source(&mut session,
+                         &one,
+                         ConflictPolicy::Add,
+                         None::<&mut ScriptedPrompter>,
+                         |_, _| {})
+            .unwrap();
+        session.collection.save(&colpath, false).unwrap();
+        session.history.save(&hispath, false).unwrap();
+        let before = (save::read(&colpath).unwrap(), save::read(&hispath).unwrap());
+
+        let mut session = Session::load(&colpath, &hispath, true).unwrap();
+        session.begin("integrate", &[]).unwrap();
+        let two = source("Two", &[("학생", "pupil"), ("사과", "apple")]);
+        let mut actions = Vec::new();
+        let summary = integrate_source(&mut session,
+                                       &two,
+                                       ConflictPolicy::Prompt,
+                                       None::<&mut ScriptedPrompter>,
+                                       |handled, _| actions.push(handled.action))
+            .unwrap();
+        session.finish().unwrap();
+        assert_eq!(vec![Action::Conflict, Action::Added], actions);
+        assert_eq!((1, 1), (summary.unresolved, summary.added));
+
+        assert_eq!(before, (save::read(&colpath).unwrap(), save::read(&hispath).unwrap()));
+        assert!(!Path::new(&Journal::path_for(&colpath)).exists());
+        assert!(!Path::new(&format!("{}.bak.1", colpath)).exists());
+    }
+
+    #[test]
+    fn scripted_decisions() {
+        let dir = temp_dir("session-scripted");
+        let (colpath, hispath) = (temp_path(&dir, "col.json"), temp_path(&dir, "his.json"));
+        let mut session = Session::load(&colpath, &hispath, false).unwrap();
+        let one = source("One", &[("학생", "student")]);
+        integrate_source(&mut session,
+                         &one,
+                         ConflictPolicy::Add,
+                         None::<&mut ScriptedPrompter>,
+                         |_, _| {})
+            .unwrap();
+        session.finish().unwrap();
+        let before = save::read(&colpath).unwrap();
+
+        // Nothing is saved before a scripted decision
+        let mut session = Session::load(&colpath, &hispath, false).unwrap();
+        let mut prompter = ScriptedPrompter::new();
+        prompter.insert("학생", "pupil", Decision::Add);
+        let two = source("Two", &[("사과", "apple"), ("학생", "pupil")]);
+        integrate_source(&mut session,
+                         &two,
+                         ConflictPolicy::Prompt,
+                         Some(&mut prompter),
+                         |_, _| {})
+            .unwrap();
+        assert_eq!(before, save::read(&colpath).unwrap());
+        session.finish().unwrap();
+        assert_eq!(2, Collection::load(&colpath).unwrap().meanings("학생").len());
+    }
+}
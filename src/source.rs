@@ -3,6 +3,7 @@ use regex::Regex;
 use std::path::Path;
 use std::io::{self, Read};
 use std::fs::File;
+use std::fmt;
 
 /*
 Where to find recognized unicode class names:
@@ -14,21 +15,35 @@ lazy_static! {
     static ref RE_VOCAB: Regex = Regex::new(
         r"((?:(?:[\-\(\)/~NIAV]|\p{Hangul})+(?:\s|:)+)+)((?:(?:\p{Han}|\s)+)?)\s*(.+)"
         ).unwrap();
+    /// A term at the start of a line: grammar markers (N, I, A and V) only
+    /// count along with Hangul or '~', so that English words aren't terms.
+    static ref RE_TERM: Regex = Regex::new(
+        r"^\s*((?:[\-\(\)/~NIAV]|\p{Hangul})*(?:~|\p{Hangul})(?:[\-\(\)/~NIAV]|\p{Hangul})*)(?:\s|:|$)"
+        ).unwrap();
 }
 
 #[derive(Debug, Clone)]
 pub struct Meaning {
     pub text: String,
     pub symbol: Option<String>,
+    /// The reading sentence the note was taken from, if any.
+    pub example: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Note {
     pub term: String,
     pub meaning: Meaning,
+    /// The (1-based) line of the source the note was read from.
+    pub lineno: usize,
 }
 impl Note {
     pub fn from_line(line: &str) -> Option<Note> {
+        Note::parse_line(line).ok()
+    }
+
+    /// Parses a note, returning the (1-based) column and a reason on failure.
+    pub fn parse_line(line: &str) -> Result<Note, (usize, String)> {
         RE_VOCAB.captures(line).map(|caps| {
             let mut korean = caps.get(1).unwrap().as_str().trim();
             if korean.ends_with(":") {
@@ -45,9 +60,22 @@ impl Note {
                     } else {
                         Some(hanja.to_string())
                     },
+                    example: None,
                 },
+                lineno: 0,
             }
-        })
+        }).ok_or_else(|| diagnose(line))
+    }
+}
+
+/// Finds out why a line could not be read as a note.
+fn diagnose(line: &str) -> (usize, String) {
+    if let Some(m) = RE_TERM.captures(line).and_then(|caps| caps.get(1)) {
+        let column = line[..m.end()].chars().count() + 1;
+        (column, format!("expected a meaning after '{}'", m.as_str()))
+    } else {
+        let column = line.chars().take_while(|c| c.is_whitespace()).count() + 1;
+        (column, "expected a Korean term at the start of the note".to_string())
     }
 }
 
@@ -57,14 +85,28 @@ pub struct Source {
     pub contents: Vec<Note>,
 }
 
+/// A line in a source that could not be read as a note.
+#[derive(Debug, Clone)]
+pub struct InvalidNote {
+    pub file: String,
+    /// 1-based line number.
+    pub lineno: usize,
+    /// 1-based column (in characters) where the problem was found.
+    pub column: usize,
+    pub line: String,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidNote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.lineno, self.column, self.reason)
+    }
+}
+
 #[derive(Debug)]
 pub enum SourceLoadError {
     Io(io::Error),
-    InvalidNote {
-        file: String,
-        lineno: usize,
-        line: String,
-    },
+    InvalidNote(InvalidNote),
 }
 
 impl From<io::Error> for SourceLoadError {
@@ -87,16 +129,36 @@ lazy_static! {
 }
 
 impl Source {
+    /// Loads a source, failing on the first line that isn't a valid note.
     pub fn load(path: &str) -> Result<Source, SourceLoadError> {
-        use self::SourceLoadError::*;
-        use self::SourceScope::*;
+        let (source, mut errors) = Source::load_lenient(path)?;
+        if errors.is_empty() {
+            Ok(source)
+        } else {
+            Err(SourceLoadError::InvalidNote(errors.remove(0)))
+        }
+    }
+
+    /// Loads a source, collecting every invalid line instead of stopping at
+    /// the first one.
+    pub fn load_lenient(path: &str) -> Result<(Source, Vec<InvalidNote>), io::Error> {
         let mut file = File::open(path)?;
         let mut text = String::new();
-        let mut title =
-            Path::new(path).file_name().map(|o| o.to_string_lossy()).unwrap().to_string();
         file.read_to_string(&mut text)?;
+        Ok(Source::parse(path, &text))
+    }
+
+    /// Reads the contents of the source file at `path`, collecting every
+    /// invalid line.
+    pub fn parse(path: &str, text: &str) -> (Source, Vec<InvalidNote>) {
+        use self::SourceScope::*;
+        let mut title = Path::new(path)
+            .file_name()
+            .map_or(path.to_string(), |o| o.to_string_lossy().to_string());
         let mut scope = Vocab;
+        let mut example: Option<String> = None;
         let mut notes = Vec::new();
+        let mut errors = Vec::new();
         for (lineno, line) in text.lines().enumerate() {
             if line.starts_with("#") {
                 if let Some(m) = RE_TITLE.find(&line) {
@@ -110,6 +172,7 @@ impl Source {
                     scope = ReadingExample;
                 } else if RE_VOCABULARY.find(&line).is_some() {
                     scope = Vocab;
+                    example = None;
                 }
                 continue;
             } else if line.is_whitespace() {
@@ -128,26 +191,138 @@ impl Source {
                         }
                     }
                     Vocab | ReadingVocab => {
-                        if let Some(note) = Note::from_line(&line) {
-                            notes.push(note);
-                        } else {
-                            return Err(InvalidNote {
-                                file: path.to_string(),
-                                lineno: lineno,
-                                line: line.to_string(),
-                            });
+                        match Note::parse_line(&line) {
+                            Ok(mut note) => {
+                                note.lineno = lineno + 1;
+                                if let ReadingVocab = scope {
+                                    note.meaning.example = example.clone();
+                                }
+                                notes.push(note);
+                            }
+                            Err((column, reason)) => {
+                                errors.push(InvalidNote {
+                                    file: path.to_string(),
+                                    lineno: lineno + 1,
+                                    column: column,
+                                    line: line.to_string(),
+                                    reason: reason,
+                                });
+                            }
                         }
                     }
                     ReadingExample => {
                         scope = ReadingVocab;
-                        // Ignore the example line
+                        example = Some(line.trim().to_string());
                     }
                 }
             }
         }
-        Ok(Source {
+        let source = Source {
             title: title,
             contents: notes,
-        })
+        };
+        (source, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::super::{Source, RE_VOCAB};
+
+    fn test_re(line: &str, e_korean: &str, e_meaning: &str, e_hanja: Option<&str>) {
+        if let Some(caps) = RE_VOCAB.captures(line) {
+            let mut korean = caps.get(1).unwrap().as_str().trim();
+            if korean.ends_with(":") {
+                korean = &korean[..korean.len() - 1];
+            }
+            let hanja = caps.get(2).unwrap().as_str().trim();
+            let hanja = if hanja != "" { Some(hanja) } else { None };
+            let meaning = caps.get(3).unwrap().as_str().trim();
+            assert_eq!(e_korean, korean);
+            assert_eq!(e_meaning, meaning);
+            assert_eq!(e_hanja, hanja);
+            //println!("{} | {} | {}", korean, hanja, meaning);
+        } else {
+            panic!("Could not read line: '{}'", line);
+        }
+    }
+
+    #[test]
+    fn vocab_re_1() {
+        let l1 = "적 tidspunkt (situation, oplevelse)";
+        test_re(l1, "적", "tidspunkt (situation, oplevelse)", None);
+    }
+
+    #[test]
+    fn vocab_re_2() {
+        let l2 = "AV~(으)ㄴ/는/(으)ㄹ 데 sted";
+        test_re(l2, "AV~(으)ㄴ/는/(으)ㄹ 데", "sted", None);
+    }
+
+    #[test]
+    fn vocab_re_3() {
+        let l3 = "~복: 服 ~tøj";
+        test_re(l3, "~복", "~tøj", Some("服"));
+    }
+
+    #[test]
+    fn vocab_re_4() {
+        let l4 = "A~(으)ㄴ가요 blød interrogativ";
+        test_re(l4, "A~(으)ㄴ가요", "blød interrogativ", None);
+    }
+
+    #[test]
+    fn vocab_re_5() {
+        let l5 = "가상 현실 virtual reality";
+        test_re(l5, "가상 현실", "virtual reality", None);
+    }
+
+    #[test]
+    fn reading_examples() {
+        let text = "# Title: Lesson 4\n\
+                    학생 elev\n\
+                    # Reading\n\
+                    저는 학생이에요.\n\
+                    저 jeg (ydmyg)\n\
+                    이에요 er\n\
+                    \n\
+                    학교에 가요.\n\
+                    학교 skole\n\
+                    # Vocabulary\n\
+                    사과 æble\n";
+        let (source, invalid) = Source::parse("lesson4.txt", text);
+        assert!(invalid.is_empty());
+        assert_eq!("Lesson 4", source.title);
+        let examples = source.contents
+            .iter()
+            .map(|n| (n.term.as_str(), n.meaning.example.as_ref().map(|e| e.as_str())))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("학생", None),
+                        ("저", Some("저는 학생이에요.")),
+                        ("이에요", Some("저는 학생이에요.")),
+                        ("학교", Some("학교에 가요.")),
+                        ("사과", None)],
+                   examples);
+        assert_eq!(9, source.contents[3].lineno);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let text = "학생 elev\n\
+                    사과\n\
+                    Very good\n\
+                    학교 skole\n  \
+                    고마워\n";
+        let (source, invalid) = Source::parse("lesson5.txt", text);
+        assert_eq!(2, source.contents.len());
+        let found = invalid.iter()
+            .map(|i| (i.lineno, i.column, i.reason.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(2, 3, "expected a meaning after '사과'"),
+                        (3, 1, "expected a Korean term at the start of the note"),
+                        (5, 6, "expected a meaning after '고마워'")],
+                   found);
+        assert_eq!("lesson5.txt:3:1: expected a Korean term at the start of the note",
+                   invalid[1].to_string());
     }
 }
@@ -0,0 +1,65 @@
+//! Builders shared by the tests of the library.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use collection::Collection;
+use source::{Meaning, Note, Source};
+
+/// A meaning without a symbol or example.
+pub fn meaning(text: &str) -> Meaning {
+    Meaning {
+        text: text.to_string(),
+        symbol: None,
+        example: None,
+    }
+}
+
+/// A note on the first line of a source.
+pub fn note(term: &str, text: &str) -> Note {
+    Note {
+        term: term.to_string(),
+        meaning: meaning(text),
+        lineno: 1,
+    }
+}
+
+/// A source with a note per (term, meaning), on the lines below its title.
+pub fn source(title: &str, notes: &[(&str, &str)]) -> Source {
+    Source {
+        title: title.to_string(),
+        contents: notes.iter()
+            .enumerate()
+            .map(|(i, &(term, text))| {
+                Note {
+                    term: term.to_string(),
+                    meaning: meaning(text),
+                    lineno: i + 2,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// A collection where every term has the meaning "-" from the source 'test'.
+pub fn collection(terms: &[&str]) -> Collection {
+    let mut collection = Collection::new();
+    for term in terms {
+        collection.add_meaning(term.to_string(), meaning("-"), "test", None);
+    }
+    collection
+}
+
+/// Returns a fresh, empty temporary directory for a test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("memori-{}", name));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Returns the path of a file in a temporary directory.
+pub fn temp_path(dir: &Path, file: &str) -> String {
+    dir.join(file).to_str().expect("INVARIANT!").to_string()
+}